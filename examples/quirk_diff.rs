@@ -0,0 +1,92 @@
+// runs a rom under the default quirks and under the given overrides side by side and
+// reports the first instruction after which their registers or display differ
+//
+// cargo run --example quirk_diff -- <rom> <cycles> <name=true|false>...
+
+use std::{env, fs, process};
+
+use chip_8_rs::{
+    chip_8::Chip8,
+    chip_8_variant::{Chip8Variant, Registers},
+    headless,
+    options::Options,
+};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [rom_path, cycles, overrides @ ..] = &args[..] else {
+        eprintln!("usage: quirk_diff <rom> <cycles> <name=true|false>...");
+        process::exit(2);
+    };
+    let rom = fs::read(rom_path).expect("rom should be readable");
+    let cycles: u64 = cycles.parse().expect("cycles should be a number");
+
+    let base = Options {
+        seed: Some(0),
+        ..Options::default()
+    };
+    let mut changed = base.clone();
+    for quirk in overrides {
+        let (name, value) = quirk
+            .split_once('=')
+            .and_then(|(name, value)| Some((name, value.parse().ok()?)))
+            .expect("overrides should look like name=true or name=false");
+        if !changed.quirks.set(name, value) {
+            eprintln!("unknown quirk {name}");
+            process::exit(2);
+        }
+    }
+
+    let mut a = Chip8::from_rom(&rom, base);
+    let mut b = Chip8::from_rom(&rom, changed);
+    for cycle in 0..cycles {
+        if a.waiting() || a.halted() || b.waiting() || b.halted() {
+            println!("stopped after {cycle} cycles without diverging (waiting or halted)");
+            return;
+        }
+        let pc = a.snapshot().pc;
+        for chip_8 in [&mut a, &mut b] {
+            headless::step(chip_8, cycle);
+        }
+        let (regs_a, regs_b) = (a.snapshot(), b.snapshot());
+        if registers_differ(&regs_a, &regs_b) || a.display() != b.display() {
+            println!("diverged at cycle {cycle}, instruction at {pc:#05x}");
+            print_registers(&regs_a, &regs_b);
+            if a.display() != b.display() {
+                print_display(&a.display_ascii(), &b.display_ascii());
+            }
+            process::exit(1);
+        }
+    }
+    println!("no divergence in {cycles} cycles");
+}
+
+fn registers_differ(a: &Registers, b: &Registers) -> bool {
+    a.v != b.v || a.i != b.i || a.pc != b.pc || a.delay_timer != b.delay_timer
+}
+
+// only the registers that differ, as `name default -> override`
+fn print_registers(a: &Registers, b: &Registers) {
+    for x in 0..16 {
+        if a.v[x] != b.v[x] {
+            println!("V{x:X}  {:02X} -> {:02X}", a.v[x], b.v[x]);
+        }
+    }
+    if a.i != b.i {
+        println!("I   {:03X} -> {:03X}", a.i, b.i);
+    }
+    if a.pc != b.pc {
+        println!("pc  {:03X} -> {:03X}", a.pc, b.pc);
+    }
+    if a.delay_timer != b.delay_timer {
+        println!("dt  {:02X} -> {:02X}", a.delay_timer, b.delay_timer);
+    }
+}
+
+// both displays next to each other, rows that differ marked with `*`
+fn print_display(a: &str, b: &str) {
+    for (row_a, row_b) in a.lines().zip(b.lines()) {
+        let mark = if row_a != row_b { '*' } else { ' ' };
+        println!("{mark} {row_a}  {row_b}");
+    }
+}
@@ -1,191 +1,1666 @@
-use pixels::{Pixels, SurfaceTexture};
+use pixels::{wgpu::PresentMode, Pixels, PixelsBuilder, SurfaceTexture};
 use rodio::{source::SignalGenerator, OutputStream, Sink};
 use std::{
-    path::Path,
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+        Arc,
+    },
+    thread,
     time::{Duration, Instant},
 };
 use winit::{
     application::ApplicationHandler,
-    event::{KeyEvent, WindowEvent},
-    event_loop::ActiveEventLoop,
-    keyboard::PhysicalKey,
+    dpi::PhysicalPosition,
+    event::{ElementState, KeyEvent, MouseButton, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow},
+    keyboard::{KeyCode, PhysicalKey},
     window::{Window, WindowId},
 };
 
 use crate::{
-    chip_8::Chip8,
+    chip_8::{Chip8, FONT},
     chip_8_variant::Chip8Variant,
+    clock::{Clock, CycleBudget, Governor},
+    config::{read_rom, Config},
+    console,
     draw_job::{DrawJob, Sprite},
+    envelope::Envelope,
+    fault::Fault,
+    input_log::{InputRecorder, InputReplay},
+    memory_view::MemoryView,
+    options::{Options, Variant, Watchpoint},
+    palette::{Filter, Palette, PixelStyle},
+    quirks::Quirks,
+    rng::Rng,
+    scenario::Scenario,
+    screenshot,
+    system_event::SystemEvent,
+    timing,
+    video::VideoRecorder,
 };
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
-const REFRESH_DURATION: Duration = Duration::from_micros(16667); // 16667
+const DEFAULT_REFRESH_HZ: f64 = 60.0;
 const SYSTEM_DURATION: Duration = Duration::from_micros(16667); // 16667
 const CYCLE_DURATION: Duration = Duration::from_micros(2000); // 1429
+const MAX_CATCH_UP: u32 = 4; // ticks owed before the backlog is dropped
+const ADAPTIVE_RANGE: f64 = 10.0; // --adaptive goes down to this fraction of the rate
+const SPEEDS: [f64; 4] = [0.5, 1.0, 2.0, 4.0];
+const DEFAULT_SPEED: usize = 1;
+const CRT_DECAY: f32 = 0.5; // glow kept per 60 Hz frame after a pixel goes dark
+const ANTI_FLICKER_FRAMES: u64 = 3; // 60 Hz frames a pixel stays lit after being erased
+const ROM_BANNER_DURATION: Duration = Duration::from_secs(2);
+const TURBO_BATCH: u32 = 10_000; // instructions per loop before checking the clock again
+const KEYPAD_LAYOUT: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF, //
+];
 
 pub struct App {
     window: Option<Window>,
     pixels: Option<Pixels>,
+    memory_view: Option<MemoryView>,
+    buffer_size: (usize, usize),
     redraw: bool,
+    fault_shown: bool,
+    occluded: bool,
+    minimized: bool, // occluded by a zero sized resize rather than Occluded
+    beeping: bool,
     _stream: OutputStream,
     sink: Sink,
+    beep_gate: Arc<AtomicBool>,
     refresh_timer: Instant,
-    cycle_timer: Instant,
-    system_timer: Instant,
+    refresh_duration: Duration,
+    follow_monitor: bool,
+    vsync: bool,
+    last_update: Instant,
+    system_clock: Clock,
+    cycle_clock: Clock,
+    cycle_budget: Option<CycleBudget>, // instructions per frame instead of a cycle rate
+    per_frame: Option<f64>,            // budget at 1x, machine cycles with accurate timing
+    governor: Option<Governor>,        // adapts per_frame with --adaptive
+    accurate_timing: bool,
+    idle_skip: bool,
     chip_8: Box<dyn Chip8Variant>,
+    rewind_buffer: VecDeque<Vec<u8>>,
+    rewind_depth: usize,
+    rewinding: bool,
+    state_path: PathBuf,
+    roms: Vec<PathBuf>,
+    rom_index: usize,
+    rom_banner_until: Option<Instant>,
+    options: Options, // what a switched to rom boots with
+    rom: Vec<u8>,
+    keys: KeyInput,
+    console: Option<Receiver<String>>, // --console commands from stdin
+    speed: usize,
+    frame_cap: Option<u32>,
+    onscreen_keypad: bool,
+    sound_bar: bool,
+    sound_bar_shown: bool,
+    shown_keys: u16, // key state the on-screen keypad was last drawn with
+    palette: Palette,
+    pixel_style: PixelStyle,
+    filter: Filter,
+    canvas: Vec<u8>,             // rgba at display resolution, see present
+    phosphor: Option<Vec<f32>>,  // per pixel glow with --crt-fade, 1.0 while lit
+    lit_until: Option<Vec<u64>>, // per pixel frame to stay lit until with --anti-flicker
+    show_registers: bool,
+    paused: bool,
+    turbo: bool,
+    selected_quirk: usize,
+    step_over_depth: Option<usize>, // pause once the stack is back down to this
+    cursor: PhysicalPosition<f64>,
+    clicked_key: Option<usize>,
+    frame: u64,
+    recorder: Option<InputRecorder>,
+    record_start: Option<Vec<u8>>, // state the recording starts from
+    replay: Option<InputReplay>,
+    screenshot_on_exit: Option<PathBuf>,
+    video: Option<VideoRecorder>,
 }
 
 // public
 impl App {
-    pub fn new<P>(path: P) -> Self
-    where
-        P: AsRef<Path>,
-    {
-        let chip_8 = Box::new(Chip8::new(path));
+    pub fn new(config: Config) -> Self {
+        let mut options = config.options.clone();
+        let scenario = config.scenario.as_ref().map(|path| {
+            let scenario = Scenario::load(path).expect("scenario file should be valid");
+            log::info!("Playing scenario {}", path.display());
+            scenario
+        });
+        if let Some(scenario) = &scenario {
+            scenario.apply(&mut options);
+        }
+        let replay = config
+            .replay
+            .as_ref()
+            .map(|path| {
+                let replay = InputReplay::load(path).expect("replay file should be valid");
+                log::info!("Replaying input from {}", path.display());
+                replay
+            })
+            .or_else(|| {
+                let replay = scenario.as_ref()?.replay()?;
+                Some(replay.expect("scenario input should be valid"))
+            });
+        if let Some(seed) = replay.as_ref().and_then(|replay| replay.seed) {
+            options.seed.get_or_insert(seed);
+        }
+        let recorder = config.record.as_ref().map(|path| {
+            // recordings always carry a seed so CXNN replays the same
+            let seed = *options.seed.get_or_insert_with(Rng::time_seed);
+            InputRecorder::create(path, seed).expect("record file should be writable")
+        });
+
+        let (rom_index, rom) = match &scenario {
+            Some(scenario) => (0, scenario.rom.clone()),
+            None => config.first_rom(),
+        };
+        let mut chip_8 = Box::new(Chip8::from_rom(&rom, options.clone()));
+        if let Some(path) = &config.load_state {
+            let state = fs::read(path).expect("state file should be readable");
+            chip_8
+                .load_state(&state)
+                .expect("state file should be valid");
+            log::info!("Loaded state from {}", path.display());
+        }
+        if let Some(scenario) = &scenario {
+            chip_8
+                .load_state(&scenario.state)
+                .expect("scenario state should be valid");
+        }
+        // an exported scenario replays the recording from where it started
+        let record_start = recorder.is_some().then(|| chip_8.save_state());
 
         let (_stream, stream_handle) = OutputStream::try_default().unwrap();
         let sink = Sink::try_new(&stream_handle).unwrap();
 
         let init_time = Instant::now();
+        let per_frame = if config.accurate_timing {
+            Some(timing::FRAME_CYCLES)
+        } else if config.adaptive {
+            // adapting needs a per frame budget, so the cycle rate becomes one
+            Some(
+                config
+                    .ipf
+                    .unwrap_or(SYSTEM_DURATION.as_secs_f64() / CYCLE_DURATION.as_secs_f64()),
+            )
+        } else {
+            config.ipf
+        };
+        let governor = per_frame
+            .filter(|_| config.adaptive)
+            .map(|per_frame| Governor::new((per_frame / ADAPTIVE_RANGE).max(1.0), per_frame));
 
-        Self {
+        let mut app = Self {
             window: None,
             pixels: None,
+            memory_view: None,
+            buffer_size: (0, 0),
             redraw: false,
+            fault_shown: false,
+            occluded: false,
+            minimized: false,
+            beeping: false,
             _stream,
             sink,
+            beep_gate: Arc::new(AtomicBool::new(false)),
             refresh_timer: init_time,
-            cycle_timer: init_time,
-            system_timer: init_time,
+            refresh_duration: Duration::from_secs_f64(
+                1.0 / config.refresh_hz.unwrap_or(DEFAULT_REFRESH_HZ),
+            ),
+            follow_monitor: config.refresh_hz.is_none(),
+            vsync: config.vsync,
+            last_update: init_time,
+            system_clock: Clock::new(SYSTEM_DURATION, MAX_CATCH_UP),
+            cycle_clock: cycle_clock(SPEEDS[DEFAULT_SPEED]),
+            cycle_budget: per_frame
+                .map(|per_frame| CycleBudget::new(per_frame * SPEEDS[DEFAULT_SPEED])),
+            per_frame,
+            governor,
+            accurate_timing: config.accurate_timing,
+            idle_skip: config.idle_skip,
             chip_8,
-        }
+            rewind_buffer: VecDeque::with_capacity(config.rewind_depth),
+            rewind_depth: config.rewind_depth,
+            rewinding: false,
+            state_path: config.rom_paths[rom_index].with_extension("state"),
+            roms: config.rom_paths,
+            rom_index,
+            rom_banner_until: None,
+            options,
+            rom,
+            keys: KeyInput {
+                deterministic: config.deterministic_input,
+                ..KeyInput::default()
+            },
+            console: config.console.then(console::spawn),
+            speed: DEFAULT_SPEED,
+            frame_cap: config.frame_cap,
+            onscreen_keypad: config.onscreen_keypad,
+            sound_bar: config.sound_bar,
+            sound_bar_shown: false,
+            shown_keys: 0,
+            palette: config.palette,
+            pixel_style: config.pixel_style,
+            filter: config.filter,
+            canvas: Vec::new(),
+            phosphor: config.crt_fade.then(Vec::new),
+            lit_until: config.anti_flicker.then(Vec::new),
+            show_registers: false,
+            paused: config.start_paused,
+            turbo: false,
+            selected_quirk: 0,
+            step_over_depth: None,
+            cursor: PhysicalPosition::default(),
+            clicked_key: None,
+            frame: 0,
+            recorder,
+            record_start,
+            replay,
+            screenshot_on_exit: config.screenshot_on_exit,
+            video: config.record_video.map(VideoRecorder::new),
+        };
+        app.replay_inputs();
+        app
     }
 }
 
 // private
 impl App {
-    fn main_loop(&mut self) {
-        if self.chip_8.sound_timer() != 0 {
-            self.sink.play();
+    fn main_loop(&mut self, event_loop: &ActiveEventLoop) {
+        for key_code in std::mem::take(&mut self.keys.pending_releases) {
+            self.send_input(key_code, ElementState::Released);
+        }
+        let commands: Vec<_> = self
+            .console
+            .as_ref()
+            .map(|console| console.try_iter().collect())
+            .unwrap_or_default();
+        for command in commands {
+            self.run_command(&command);
         }
 
-        if self.system_timer.elapsed() >= SYSTEM_DURATION {
-            self.system_timer = Instant::now();
-            self.chip_8.decrement_timers();
-            if self.chip_8.sound_timer() == 0 {
-                self.sink.pause();
-            }
+        if self.occluded {
+            return;
         }
 
-        if self.cycle_timer.elapsed() >= CYCLE_DURATION {
-            self.cycle_timer = Instant::now();
-            if !self.chip_8.waiting() {
-                self.chip_8.instruction_cycle();
+        let now = Instant::now();
+        // paused time is dropped rather than owed, the frame keeps being presented
+        let elapsed = if self.paused {
+            Duration::ZERO
+        } else {
+            now - self.last_update
+        };
+        self.last_update = now;
+        let ticks = self.system_clock.advance(elapsed);
+        let work_start = Instant::now();
+        for _ in 0..ticks {
+            // a breakpoint pauses mid frame, the rest of the owed frames are dropped
+            if self.paused {
+                break;
+            }
+            if self.rewinding {
+                self.rewind();
+            } else {
+                self.record_snapshot();
+                self.chip_8.decrement_timers();
+                self.frame += 1;
+                self.replay_inputs();
+                self.apply_staged_keys();
+                if let Some(budget) = &mut self.cycle_budget {
+                    let cycles = budget.next_frame();
+                    if self.accurate_timing {
+                        self.run_machine_cycles(cycles);
+                    } else {
+                        self.run_cycles(cycles);
+                    }
+                }
             }
-            self.render();
         }
 
-        if self.refresh_timer.elapsed() >= REFRESH_DURATION {
-            self.refresh_timer = Instant::now();
-            if self.redraw {
-                self.pixels.as_ref().unwrap().render().unwrap();
+        if ticks > 0 && !self.paused && !self.rewinding {
+            self.govern(work_start.elapsed() / ticks);
+        }
+
+        if self.cycle_budget.is_none() {
+            let cycles = self.cycle_clock.advance(elapsed);
+            self.run_cycles(cycles);
+        }
+        if self.turbo && !self.paused {
+            self.run_turbo();
+        }
+        self.handle_system_events(event_loop);
+
+        self.update_beep();
+
+        if tick(&mut self.refresh_timer, self.refresh_duration) {
+            // draws are applied once per frame, collisions are already resolved by the core
+            self.render();
+            if let Some(video) = &mut self.video {
+                video.capture(self.chip_8.as_ref(), self.refresh_duration);
+            }
+            if self.onscreen_keypad && self.chip_8.key_state() != self.shown_keys {
+                self.draw_keypad();
+                self.redraw = true;
+            }
+            // the overlay covers pixels sprites draw into and the fade and anti-flicker
+            // change pixels each frame, either way the frame is rebuilt from the display
+            if self.show_registers
+                || self.phosphor.is_some()
+                || self.lit_until.is_some()
+                || self.rom_banner_until.is_some()
+                || self.sound_bar_shown
+            {
+                self.repaint();
+            }
+            if self.show_registers {
+                self.draw_registers();
+            }
+            if self.sound_bar {
+                self.draw_sound_bar();
+            }
+            if let Some(until) = self.rom_banner_until {
+                if Instant::now() < until {
+                    self.draw_rom_banner();
+                } else {
+                    self.rom_banner_until = None;
+                }
+            }
+            if let Some(fault) = self.chip_8.fault().filter(|_| !self.fault_shown) {
+                self.draw_fault(fault);
+            }
+            // redraw covers what the window paints over the display itself
+            if self.redraw || self.chip_8.display_dirty() {
+                self.present();
                 self.redraw = false;
+                self.chip_8.mark_display_clean();
+            }
+            if let Some(memory_view) = &mut self.memory_view {
+                memory_view.draw(self.chip_8.as_ref());
             }
         }
 
         self.window.as_ref().unwrap().request_redraw();
+
+        if let Some(frame_cap) = self.frame_cap {
+            // sleep off the rest of the frame instead of spinning, the clocks make up the time
+            let frame_end = now + Duration::from_secs_f64(1.0 / frame_cap as f64);
+            if let Some(remaining) = frame_end.checked_duration_since(Instant::now()) {
+                thread::sleep(remaining);
+            }
+        }
+    }
+
+    fn run_cycles(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if self.rewinding || self.paused || self.chip_8.waiting() || self.idle() {
+                break;
+            }
+            self.chip_8.instruction_cycle();
+            self.check_debug_stops();
+        }
+    }
+
+    // with --idle-skip a rom spinning on the delay timer stops running until the timers
+    // tick instead of burning the rest of the frame's cycles on the same loop
+    fn idle(&self) -> bool {
+        self.idle_skip && self.chip_8.idling()
+    }
+
+    // instructions cost their VIP machine cycles, so draw heavy code runs fewer per frame
+    fn run_machine_cycles(&mut self, budget: u32) {
+        let start = self.chip_8.machine_cycles();
+        let budget = budget as u64;
+        let mut spent = 0;
+        while spent < budget {
+            if self.rewinding
+                || self.paused
+                || self.chip_8.waiting()
+                || self.chip_8.halted()
+                || self.idle()
+            {
+                break;
+            }
+            self.chip_8.instruction_cycle();
+            self.check_debug_stops();
+            spent = self.chip_8.machine_cycles() - start;
+        }
+        if spent > budget {
+            if let Some(cycle_budget) = &mut self.cycle_budget {
+                cycle_budget.overspend((spent - budget) as u32);
+            }
+        }
+    }
+
+    // full speed until the rom draws a sprite or waits for a key, to skip load screens
+    fn run_turbo(&mut self) {
+        let draws = self.chip_8.opcode_counts()[0xD];
+        for _ in 0..TURBO_BATCH {
+            if self.rewinding || self.paused || self.chip_8.waiting() || self.chip_8.halted() {
+                break;
+            }
+            self.chip_8.instruction_cycle();
+            self.check_debug_stops();
+            if self.chip_8.opcode_counts()[0xD] != draws {
+                break;
+            }
+        }
+        if self.paused
+            || self.chip_8.waiting()
+            || self.chip_8.halted()
+            || self.chip_8.opcode_counts()[0xD] != draws
+        {
+            self.turbo = false;
+            log::info!("Turbo off");
+        }
+    }
+
+    // with --adaptive, slows the cpu down when running a frame's instructions takes too
+    // much of the frame and speeds it back up once it doesn't
+    fn govern(&mut self, frame_work: Duration) {
+        let Some(governor) = &mut self.governor else {
+            return;
+        };
+        let previous = governor.per_frame();
+        if let Some(per_frame) = governor.adjust(frame_work, SYSTEM_DURATION) {
+            if per_frame < previous {
+                log::info!("Throttled to {:.1} per frame", per_frame);
+            }
+            self.per_frame = Some(per_frame);
+            if let Some(budget) = &mut self.cycle_budget {
+                budget.set_per_frame(per_frame * SPEEDS[self.speed]);
+            }
+        } else if governor.settled() {
+            log::info!("Settled at {:.1} per frame", governor.per_frame());
+        }
+    }
+
+    fn toggle_memory_view(&mut self, event_loop: &ActiveEventLoop) {
+        self.memory_view = match self.memory_view {
+            Some(_) => None,
+            None => Some(MemoryView::open(event_loop)),
+        };
+    }
+
+    // keys pressed with the memory view focused go to the machine like any others
+    fn memory_view_event(&mut self, event_loop: &ActiveEventLoop, event: WindowEvent) {
+        match event {
+            WindowEvent::Resized(size) => self.memory_view.as_mut().unwrap().resize(size),
+            WindowEvent::CloseRequested => self.memory_view = None,
+            WindowEvent::CursorMoved { position, .. } => {
+                self.memory_view.as_mut().unwrap().set_cursor(position)
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => self.click_listing(),
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key_code),
+                        state,
+                        repeat: false,
+                        ..
+                    },
+                is_synthetic: false,
+                ..
+            } => self.handle_window_key(event_loop, key_code, state),
+            _ => (),
+        }
+    }
+
+    // clicking a line of the memory view's listing sets or clears a breakpoint there, kept
+    // for roms switched to as well like those from --break
+    fn click_listing(&mut self) {
+        let memory_size = self.chip_8.memory().len();
+        let memory_view = self.memory_view.as_ref().unwrap();
+        let Some(address) = memory_view.listed_address(memory_size) else {
+            return;
+        };
+        let set = self.chip_8.toggle_breakpoint(address);
+        self.options.breakpoints = self.chip_8.breakpoints().clone();
+        log::info!(
+            "Breakpoint {} at {:03X}",
+            if set { "set" } else { "cleared" },
+            address
+        );
+    }
+
+    // F10 needs the event loop to open a window, everything else goes to handle_key
+    fn handle_window_key(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        key_code: KeyCode,
+        state: ElementState,
+    ) {
+        if key_code == KeyCode::F10 {
+            if state.is_pressed() {
+                self.toggle_memory_view(event_loop);
+            }
+        } else {
+            self.handle_key(key_code, state);
+        }
+    }
+
+    fn set_occluded(&mut self, event_loop: &ActiveEventLoop, occluded: bool) {
+        if self.occluded == occluded {
+            return;
+        }
+        self.occluded = occluded;
+        if occluded {
+            event_loop.set_control_flow(ControlFlow::Wait);
+            self.beep_gate.store(false, Ordering::Relaxed);
+            self.beeping = false;
+        } else {
+            event_loop.set_control_flow(ControlFlow::Poll);
+            self.snap_timers();
+            self.window.as_ref().unwrap().request_redraw();
+        }
+    }
+
+    fn snap_timers(&mut self) {
+        let now = Instant::now();
+        self.refresh_timer = now;
+        self.last_update = now;
+        self.system_clock.reset();
+        self.cycle_clock.reset();
+    }
+
+    fn update_beep(&mut self) {
+        let beeping = !self.paused && self.chip_8.sound_active();
+        if beeping == self.beeping {
+            return;
+        }
+        self.beeping = beeping;
+        self.beep_gate.store(beeping, Ordering::Relaxed);
+    }
+
+    fn record_snapshot(&mut self) {
+        if self.rewind_depth == 0 {
+            return;
+        }
+        if self.rewind_buffer.len() == self.rewind_depth {
+            self.rewind_buffer.pop_front();
+        }
+        self.rewind_buffer.push_back(self.chip_8.save_state());
+    }
+
+    fn rewind(&mut self) {
+        if let Some(state) = self.rewind_buffer.pop_back() {
+            self.chip_8
+                .load_state(&state)
+                .expect("rewind snapshot should be valid");
+            self.repaint();
+        }
+    }
+
+    fn save_state(&self) {
+        match fs::write(&self.state_path, self.chip_8.save_state()) {
+            Ok(()) => log::info!("Saved state to {}", self.state_path.display()),
+            Err(err) => log::error!("Failed to save state: {}", err),
+        }
+    }
+
+    // with --record running the bundle starts where the recording did and carries the
+    // input so far, so it plays back to this moment, otherwise it holds the machine as is
+    fn export_scenario(&mut self) {
+        let (state, input) = match (&mut self.recorder, &self.record_start) {
+            (Some(recorder), Some(start)) => match recorder.recorded() {
+                Ok(input) => (start.clone(), input),
+                Err(err) => {
+                    log::error!("Failed to read back the recording: {}", err);
+                    return;
+                }
+            },
+            _ => (self.chip_8.save_state(), String::new()),
+        };
+        let scenario = Scenario {
+            rom: self.rom.clone(),
+            variant: self.options.variant,
+            extensions: self.options.extensions,
+            raw_image: self.options.raw_image,
+            vip_boot: self.options.vip_boot,
+            fill: self.options.fill,
+            seed: self.options.seed,
+            quirks: self.chip_8.quirks().clone(),
+            state,
+            input,
+        };
+        let path = self.state_path.with_extension("c8s");
+        match scenario.save(&path) {
+            Ok(()) => log::info!("Exported scenario to {}", path.display()),
+            Err(err) => log::error!("Failed to export scenario: {}", err),
+        }
+    }
+
+    // rewinding past a reset would bring the old run back, so the history goes too
+    fn reset(&mut self) {
+        self.chip_8.reset();
+        self.step_over_depth = None;
+        self.rewind_buffer.clear();
+        log::info!("Reset");
+    }
+
+    // steps through the roms given on the command line, skipping any that can't be read,
+    // and boots the next one fresh
+    fn switch_rom(&mut self, forward: bool) {
+        if self.recorder.is_some() || self.replay.is_some() {
+            log::warn!("Can't switch roms while recording or replaying input");
+            return;
+        }
+        let count = self.roms.len();
+        for step in 1..count {
+            let index = if forward {
+                (self.rom_index + step) % count
+            } else {
+                (self.rom_index + count - step) % count
+            };
+            let Some(rom) = read_rom(&self.roms[index]) else {
+                continue;
+            };
+            self.boot(rom);
+            self.rom_index = index;
+            self.state_path = self.roms[index].with_extension("state");
+            self.rom_banner_until = Some(Instant::now() + ROM_BANNER_DURATION);
+            self.update_title();
+            log::info!(
+                "Switched to {} ({} of {})",
+                self.roms[index].display(),
+                index + 1,
+                count
+            );
+            return;
+        }
+    }
+
+    // F12 cycles CHIP-8, SUPER-CHIP and XO-CHIP, rebooting the same rom with the next
+    // variant's instruction groups and memory size while quirks toggled so far carry over
+    fn switch_variant(&mut self) {
+        if self.recorder.is_some() || self.replay.is_some() {
+            log::warn!("Can't switch variants while recording or replaying input");
+            return;
+        }
+        self.options.variant = match self.options.variant {
+            Variant::Chip8 => Variant::SuperChip,
+            Variant::SuperChip => Variant::XoChip,
+            Variant::XoChip => Variant::Chip8,
+        };
+        self.options.extensions = None;
+        self.boot(self.rom.clone());
+        self.update_title();
+        log::info!("Switched to {}", self.options.variant);
+    }
+
+    // a fresh machine for `rom` with the command line's options and the quirks toggled
+    // so far, the history and effects of the last one are dropped
+    fn boot(&mut self, rom: Vec<u8>) {
+        self.options.quirks = self.chip_8.quirks().clone();
+        self.chip_8 = Box::new(Chip8::from_rom(&rom, self.options.clone()));
+        self.rom = rom;
+        self.step_over_depth = None;
+        self.rewind_buffer.clear();
+        if let Some(phosphor) = &mut self.phosphor {
+            phosphor.clear();
+        }
+        if let Some(lit_until) = &mut self.lit_until {
+            lit_until.clear();
+        }
+        // the new variant can have another resolution, repaint() resizes the buffer to it
+        self.repaint();
+    }
+
+    fn handle_key(&mut self, key_code: KeyCode, state: ElementState) {
+        match key_code {
+            KeyCode::Backspace => self.rewinding = state.is_pressed(),
+            KeyCode::KeyP if state.is_pressed() => self.set_paused(!self.paused),
+            KeyCode::Tab if state.is_pressed() => {
+                self.turbo = !self.turbo;
+                log::info!("Turbo {}", if self.turbo { "on" } else { "off" });
+            }
+            KeyCode::F1 if state.is_pressed() => self.toggle_registers(),
+            KeyCode::F2 if state.is_pressed() => self.select_next_quirk(),
+            KeyCode::F3 if state.is_pressed() => self.toggle_quirk(),
+            KeyCode::F4 if state.is_pressed() => self.toggle_timers_frozen(),
+            KeyCode::F5 if state.is_pressed() => self.save_state(),
+            KeyCode::F6 if state.is_pressed() => self.reset(),
+            KeyCode::F7 if state.is_pressed() => self.step(),
+            KeyCode::F11 if state.is_pressed() => self.step_over(),
+            KeyCode::F12 if state.is_pressed() => self.switch_variant(),
+            KeyCode::F8 if state.is_pressed() => self.export_scenario(),
+            KeyCode::PageUp if state.is_pressed() => self.switch_rom(false),
+            KeyCode::PageDown if state.is_pressed() => self.switch_rom(true),
+            KeyCode::F9 if state.is_pressed() => self.print_opcode_report(),
+            KeyCode::Minus if state.is_pressed() => self.set_speed(self.speed.saturating_sub(1)),
+            KeyCode::Equal if state.is_pressed() => {
+                self.set_speed((self.speed + 1).min(SPEEDS.len() - 1))
+            }
+            _ => self.handle_keypad(key_code, state),
+        }
+    }
+
+    // a breakpoint pauses in front of its instruction, which runs once stepped over or
+    // resumed, a watchpoint pauses after the instruction that changed it
+    fn check_debug_stops(&mut self) {
+        if self.log_debug_stops() {
+            self.set_paused(true);
+        } else if self
+            .step_over_depth
+            .is_some_and(|depth| self.chip_8.stack().len() <= depth)
+        {
+            self.log_registers("Returned");
+            self.set_paused(true);
+        }
+    }
+
+    fn log_debug_stops(&self) -> bool {
+        if let Some(hit) = self.chip_8.watch_hit() {
+            log::info!(
+                "Watchpoint {} changed from {:02X} to {:02X} by {:04X} at {:03X}",
+                hit.watchpoint,
+                hit.old,
+                hit.new,
+                hit.ir,
+                hit.pc
+            );
+            self.log_registers("Watchpoint hit");
+            return true;
+        }
+        if self.chip_8.at_breakpoint() {
+            self.log_registers("Breakpoint hit");
+            return true;
+        }
+        false
+    }
+
+    // while paused runs the next instruction, then logs where that left the machine, keys
+    // staged since the last step land first so they don't all arrive at once on resume
+    fn step(&mut self) {
+        if !self.paused {
+            return;
+        }
+        self.apply_staged_keys();
+        self.chip_8.instruction_cycle();
+        if !self.log_debug_stops() {
+            self.log_registers("Stepped");
+        }
+    }
+
+    // a call runs through to its return, comparing stack depths so recursion inside it
+    // doesn't stop early, anything other than a call is a plain step
+    fn step_over(&mut self) {
+        if !self.paused {
+            return;
+        }
+        let depth = self.chip_8.stack().len();
+        self.apply_staged_keys();
+        self.chip_8.instruction_cycle();
+        if self.log_debug_stops() {
+            return;
+        }
+        if self.chip_8.stack().len() > depth {
+            log::info!(
+                "Stepping over the call to {:03X}",
+                self.chip_8.snapshot().pc
+            );
+            self.set_paused(false);
+            self.step_over_depth = Some(depth);
+        } else {
+            self.log_registers("Stepped");
+        }
+    }
+
+    // `V5 = 03` or `2A0 = FF` pokes a register or a memory byte while paused, values in hex
+    fn run_command(&mut self, command: &str) {
+        let command = command.trim();
+        if command.is_empty() {
+            return;
+        }
+        let Some((target, value)) = command.split_once('=') else {
+            log::warn!("Expected `V5 = 03` or `2A0 = FF`, got {command}");
+            return;
+        };
+        let target: Watchpoint = match target.trim().parse() {
+            Ok(target) => target,
+            Err(err) => {
+                log::warn!("{err}");
+                return;
+            }
+        };
+        let value = value.trim();
+        let Ok(value) = u8::from_str_radix(value.trim_start_matches("0x"), 16) else {
+            log::warn!("{value} isn't a hex byte");
+            return;
+        };
+        if !self.paused {
+            log::warn!("Pause first to poke {target}");
+            return;
+        }
+        let old = match target {
+            Watchpoint::V(x) => self.chip_8.set_register(x, value),
+            Watchpoint::Memory(address) => self.chip_8.poke_memory(address, value),
+            Watchpoint::I => {
+                log::warn!("I can't be poked, only V registers and memory");
+                return;
+            }
+        };
+        match old {
+            Some(old) => log::info!("Poked {target} from {old:02X} to {value:02X}"),
+            None => log::warn!("{target} is out of range"),
+        }
+    }
+
+    fn handle_system_events(&mut self, event_loop: &ActiveEventLoop) {
+        while let Some(event) = self.chip_8.poll_system_event() {
+            match event {
+                SystemEvent::Exit => {
+                    log::info!("The rom exited, closing");
+                    event_loop.exit();
+                }
+                SystemEvent::Fault(_) => self.log_registers("Fault"),
+                SystemEvent::ResolutionChanged { hires } => {
+                    log::debug!("Switched to {}", if hires { "hires" } else { "lores" })
+                }
+            }
+        }
+    }
+
+    fn log_registers(&self, event: &str) {
+        let registers = self.chip_8.snapshot();
+        log::info!(
+            "{} at {:03X}: V {:02X?} I {:03X} delay {:02X} sound {:02X}",
+            event,
+            registers.pc,
+            registers.v,
+            registers.i,
+            registers.delay_timer,
+            registers.sound_timer
+        );
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if !paused && self.chip_8.at_breakpoint() {
+            // run the instruction under the breakpoint so it doesn't stop there again
+            self.chip_8.instruction_cycle();
+        }
+        self.paused = paused;
+        self.step_over_depth = None;
+        self.update_title();
+        log::info!("{}", if paused { "Paused" } else { "Resumed" });
+    }
+
+    fn update_title(&mut self) {
+        let mut title = self.options.variant.to_string();
+        if self.roms.len() > 1 {
+            let path = &self.roms[self.rom_index];
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            title += &format!(" - {}", name.to_string_lossy());
+        }
+        if self.paused {
+            title += " (paused)";
+        }
+        if let Some(window) = &self.window {
+            window.set_title(&title);
+        }
+    }
+
+    fn toggle_registers(&mut self) {
+        self.show_registers = !self.show_registers;
+        if !self.show_registers {
+            self.repaint();
+        }
+    }
+
+    // releases wait for the next loop so a release + press pair from emulated key
+    // repeat cancels out and the key stays held for the rom
+    fn handle_keypad(&mut self, key_code: KeyCode, state: ElementState) {
+        match state {
+            ElementState::Released => {
+                if !self.keys.pending_releases.contains(&key_code) {
+                    self.keys.pending_releases.push(key_code);
+                }
+            }
+            ElementState::Pressed => {
+                if let Some(index) = self
+                    .keys
+                    .pending_releases
+                    .iter()
+                    .position(|&k| k == key_code)
+                {
+                    self.keys.pending_releases.swap_remove(index);
+                } else {
+                    self.send_input(key_code, state);
+                }
+            }
+        }
+    }
+
+    fn release_all_keys(&mut self) {
+        self.rewinding = false;
+        // a replay holds its own keys
+        if self.replay.is_some() {
+            return;
+        }
+        for (key, state) in self.keys.release_all(self.chip_8.key_state()) {
+            self.apply_key(key, state);
+        }
+    }
+
+    fn select_next_quirk(&mut self) {
+        self.selected_quirk = (self.selected_quirk + 1) % Quirks::NAMES.len();
+        let name = Quirks::NAMES[self.selected_quirk];
+        let value = self.chip_8.quirks().get(name).unwrap();
+        log::info!("Selected quirk {} ({})", name, value);
+    }
+
+    fn toggle_quirk(&mut self) {
+        let name = Quirks::NAMES[self.selected_quirk];
+        let value = !self.chip_8.quirks().get(name).unwrap();
+        self.chip_8.set_quirk(name, value);
+        log::info!("Quirk {} set to {}", name, value);
+    }
+
+    fn toggle_timers_frozen(&mut self) {
+        let frozen = !self.chip_8.timers_frozen();
+        self.chip_8.set_timers_frozen(frozen);
+        log::info!("Timers {}", if frozen { "frozen" } else { "running" });
+    }
+
+    // live keypad input is recorded with --record and ignored while a replay runs
+    fn send_input(&mut self, key_code: KeyCode, state: ElementState) {
+        if self.replay.is_some() {
+            return;
+        }
+        if let Some(key) = hex_key(key_code) {
+            self.send_key(key, state);
+        }
+    }
+
+    fn send_key(&mut self, key: usize, state: ElementState) {
+        if self.replay.is_some() {
+            return;
+        }
+        if let Some((key, state)) = self.keys.send(key, state) {
+            self.apply_key(key, state);
+        }
+    }
+
+    fn apply_staged_keys(&mut self) {
+        for (key, state) in std::mem::take(&mut self.keys.staged) {
+            self.apply_key(key, state);
+        }
+    }
+
+    fn apply_key(&mut self, key: usize, state: ElementState) {
+        self.record_input(key, state);
+        self.chip_8.set_key(key, state.is_pressed());
+    }
+
+    fn record_input(&mut self, key: usize, state: ElementState) {
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.record(self.frame, key, state) {
+                log::error!("Failed to record input: {}", err);
+                self.recorder = None;
+            }
+        }
+    }
+
+    fn replay_inputs(&mut self) {
+        let Some(replay) = &mut self.replay else {
+            return;
+        };
+        while let Some((key, state)) = replay.due(self.frame) {
+            self.chip_8.set_key(key, state.is_pressed());
+        }
+        if replay.finished() {
+            log::info!("Replay finished at frame {}", self.frame);
+            self.replay = None;
+        }
+    }
+
+    fn handle_click(&mut self, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                self.clicked_key = self.keypad_hit();
+                if let Some(key) = self.clicked_key {
+                    self.send_key(key, state);
+                }
+            }
+            ElementState::Released => {
+                if let Some(key) = self.clicked_key.take() {
+                    self.send_key(key, state);
+                }
+            }
+        }
+    }
+
+    fn keypad_hit(&self) -> Option<usize> {
+        let (width, height) = self.buffer_size;
+        let position = (self.cursor.x as f32, self.cursor.y as f32);
+        let (x, y) = self.pixels.as_ref()?.window_pos_to_pixel(position).ok()?;
+        let cell = self.cell();
+        let (x, y) = (x / cell, y / cell);
+        let row = y.checked_sub(height)? / (width / 8);
+        let col = x / (width / 4);
+        KEYPAD_LAYOUT.get(4 * row + col).copied()
+    }
+
+    fn set_speed(&mut self, speed: usize) {
+        self.speed = speed;
+        self.cycle_clock = cycle_clock(SPEEDS[speed]);
+        if let (Some(budget), Some(per_frame)) = (&mut self.cycle_budget, self.per_frame) {
+            budget.set_per_frame(per_frame * SPEEDS[speed]);
+        }
+        log::info!("Clock speed set to {}x", SPEEDS[speed]);
+    }
+
+    fn print_opcode_report(&self) {
+        let counts = self.chip_8.opcode_counts();
+        let total = counts.iter().sum::<u64>().max(1);
+        println!("opcode {:>12} {:>7}", "count", "share");
+        for (opcode, count) in counts.iter().enumerate() {
+            let share = 100.0 * *count as f64 / total as f64;
+            println!("{:X}___  {:>12} {:>6.2}%", opcode, count, share);
+        }
+        println!("total  {:>12}", self.chip_8.instruction_count());
     }
 
     fn render(&mut self) {
+        let mut repainting = false;
         while let Some(job) = self.chip_8.poll_draw_queue() {
             match job {
+                // a repaint from the display buffer already covers whatever follows
+                _ if repainting => (),
                 DrawJob::Draw(sprite) => {
                     self.draw_sprite(sprite);
                 }
                 DrawJob::Clear => self.clear_screen(),
+                DrawJob::Repaint => repainting = true,
+            }
+        }
+        if repainting {
+            self.repaint();
+        }
+    }
+
+    fn keypad_height(&self, width: usize) -> usize {
+        if self.onscreen_keypad {
+            width / 2
+        } else {
+            0
+        }
+    }
+
+    // 4x4 grid of cells under the display, each labelled with its hex glyph
+    fn draw_keypad(&mut self) {
+        let (width, height) = self.buffer_size;
+        let (cell_width, cell_height) = (width / 4, width / 8);
+        let key_state = self.chip_8.key_state();
+        self.shown_keys = key_state;
+        let frame = &mut self.canvas;
+
+        for (i, pixel) in frame[4 * width * height..].chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % width % cell_width, i / width % cell_height);
+            let cell = i / width / cell_height * 4 + i % width / cell_width;
+            let held = KEYPAD_LAYOUT
+                .get(cell)
+                .is_some_and(|&key| key_state & 1 << key != 0);
+            let border = x == 0 || y == 0 || x == cell_width - 1 || y == cell_height - 1;
+            let value = match (border, held) {
+                (true, _) => 0x60,
+                (false, true) => 0x48,
+                (false, false) => 0x28,
+            };
+            pixel.copy_from_slice(&[value, value, value, 0xff]);
+        }
+        for (cell, key) in KEYPAD_LAYOUT.iter().enumerate() {
+            let left = cell % 4 * cell_width + (cell_width - 4) / 2;
+            let top = height + cell / 4 * cell_height + (cell_height - 5) / 2;
+            for (row, bits) in FONT[5 * key..5 * key + 5].iter().enumerate() {
+                for col in 0..4 {
+                    if bits & (0x80 >> col) != 0 {
+                        let index = 4 * (left + col + width * (top + row));
+                        frame[index..index + 4].copy_from_slice(&[0xff; 4]);
+                    }
+                }
             }
-            self.redraw = true;
         }
     }
 
     fn clear_screen(&mut self) {
-        let frame = self.pixels.as_mut().unwrap().frame_mut();
-        for pixel in frame.chunks_exact_mut(4) {
-            pixel[0] = 0x00;
-            pixel[1] = 0x00;
-            pixel[2] = 0x00;
-            pixel[3] = 0xff;
+        let (width, height) = self.buffer_size;
+        let background = self.palette.color(0);
+        let frame = &mut self.canvas;
+        for pixel in frame[..4 * width * height].chunks_exact_mut(4) {
+            pixel.copy_from_slice(&background);
         }
     }
 
-    fn draw_sprite(&mut self, sprite: Sprite) {
-        let n_x = sprite.v_x & 0x3F;
-        let n_y = sprite.v_y & 0x1F;
-        let mut collision = false;
+    // frame buffer pixels per canvas pixel, a filter and a pixel style never both apply
+    fn cell(&self) -> usize {
+        match self.filter {
+            Filter::Nearest => self.pixel_style.cell(),
+            filter => filter.cell(),
+        }
+    }
 
-        let frame = self.pixels.as_mut().unwrap().frame_mut();
+    // everything draws into the canvas at display resolution, pixel styles then blow
+    // each canvas pixel up into a shaped cell of the real frame
+    fn present(&mut self) {
+        self.fill_frame();
+        let Err(error) = self.pixels.as_ref().unwrap().render() else {
+            return;
+        };
+        // the surface is lost on gpu resets, monitor changes and suspends, so build a new
+        // one and try the frame again, a failure there is left for the next frame to retry
+        log::warn!("Rendering failed: {error}, recreating the surface");
+        let pixels = match self.create_pixels(self.window.as_ref().unwrap()) {
+            Ok(pixels) => pixels,
+            Err(error) => {
+                log::error!("Couldn't recreate the surface: {error}");
+                return;
+            }
+        };
+        self.pixels = Some(pixels);
+        self.fill_frame();
+        match self.pixels.as_ref().unwrap().render() {
+            Ok(()) => log::info!("Recovered the surface"),
+            Err(error) => log::error!("Rendering still fails: {error}"),
+        }
+    }
 
-        for (i, row) in sprite.buf.iter().enumerate() {
-            for j in 0..8 {
-                if (row & (1 << (7 - j))) >> (7 - j) == 1 {
-                    // flip (x + j, y + i) -> 4 * (x + j + width * (y + i))
-                    if (n_x + j) >= WIDTH {
-                        continue;
-                    }
-                    if (n_y + i) >= HEIGHT {
-                        continue;
+    fn create_pixels(&self, window: &Window) -> Result<Pixels, pixels::Error> {
+        let size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(size.width, size.height, window);
+        let (width, height) = self.buffer_size;
+        let buffer_height = height + self.keypad_height(width);
+        // vsync waits for the display so frames never tear, at the cost of up to a frame
+        // of latency, without it frames are presented as soon as they are rendered
+        let present_mode = if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        };
+        let cell = self.cell();
+        PixelsBuilder::new(
+            (cell * width) as u32,
+            (cell * buffer_height) as u32,
+            surface_texture,
+        )
+        .present_mode(present_mode)
+        .build()
+    }
+
+    fn fill_frame(&mut self) {
+        let (width, _) = self.buffer_size;
+        let cell = self.cell();
+        let frame = self.pixels.as_mut().unwrap().frame_mut();
+        if self.filter == Filter::Scale2x {
+            scale2x(&self.canvas, width, frame);
+        } else if self.filter == Filter::Linear {
+            upscale_linear(&self.canvas, width, cell, frame);
+        } else if cell == 1 {
+            frame.copy_from_slice(&self.canvas);
+        } else {
+            let background = self.palette.color(0);
+            for (i, color) in self.canvas.chunks_exact(4).enumerate() {
+                let color = [color[0], color[1], color[2], color[3]];
+                let (x, y) = (cell * (i % width), cell * (i / width));
+                for dy in 0..cell {
+                    for dx in 0..cell {
+                        let shade = self.pixel_style.shade(dx, dy);
+                        let index = 4 * (x + dx + cell * width * (y + dy));
+                        frame[index..index + 4].copy_from_slice(&blend(background, color, shade));
                     }
-                    let index = 4 * (n_x + j + WIDTH * (n_y + i));
-                    if !collision {
-                        let check = frame[index] | frame[index + 1] | frame[index + 2];
-                        if check > 0 {
-                            collision = true;
-                        }
+                }
+            }
+        }
+    }
+
+    fn repaint(&mut self) {
+        let (width, height) = self.chip_8.resolution();
+        if self.buffer_size != (width, height) {
+            let buffer_height = height + self.keypad_height(width);
+            let cell = self.cell();
+            self.pixels
+                .as_mut()
+                .unwrap()
+                .resize_buffer((cell * width) as u32, (cell * buffer_height) as u32)
+                .unwrap();
+            self.canvas = vec![0; 4 * width * buffer_height];
+            self.buffer_size = (width, height);
+            if self.onscreen_keypad {
+                self.draw_keypad();
+            }
+        }
+
+        // the decay is per 60 Hz frame, so scale it to however often we present
+        let decay = CRT_DECAY.powf(self.refresh_duration.as_secs_f32() * 60.0);
+        let display = self.chip_8.display();
+        let frame = &mut self.canvas;
+        // pixels erased in the last few frames still show lit, which hides the flicker of
+        // sprites erased and redrawn every frame without touching the display
+        let mut lit_until = self.lit_until.as_mut().map(|lit_until| {
+            if lit_until.len() != width * height {
+                *lit_until = vec![0; width * height];
+            }
+            (lit_until, self.frame)
+        });
+        let mut shown_value = |i: usize| {
+            let value = pixel_value(display, i);
+            match &mut lit_until {
+                Some((lit_until, frame)) if value != 0 => {
+                    lit_until[i] = *frame + ANTI_FLICKER_FRAMES;
+                    value
+                }
+                Some((lit_until, frame)) if *frame < lit_until[i] => 1,
+                _ => value,
+            }
+        };
+        match &mut self.phosphor {
+            Some(phosphor) => {
+                if phosphor.len() != width * height {
+                    *phosphor = vec![0.0; width * height];
+                }
+                let (dark, lit) = (self.palette.color(0), self.palette.color(1));
+                for (i, pixel) in frame[..4 * width * height].chunks_exact_mut(4).enumerate() {
+                    let value = shown_value(i);
+                    if value != 0 {
+                        phosphor[i] = 1.0;
+                        pixel.copy_from_slice(&self.palette.color(value));
+                    } else {
+                        phosphor[i] *= decay;
+                        pixel.copy_from_slice(&blend(dark, lit, phosphor[i]));
                     }
-                    frame[index] ^= 0xff;
-                    frame[index + 1] ^= 0xff;
-                    frame[index + 2] ^= 0xff;
-                    frame[index + 3] = 0xff;
+                }
+            }
+            None => {
+                for (i, pixel) in frame[..4 * width * height].chunks_exact_mut(4).enumerate() {
+                    pixel.copy_from_slice(&self.palette.color(shown_value(i)));
                 }
             }
         }
-        self.chip_8.set_collision(collision);
+        self.fault_shown = false;
+        self.redraw = true;
     }
+
+    // red banner across the top reading `E<kind> <pc> <instruction>` in hex
+    fn draw_fault(&mut self, fault: Fault) {
+        let text = format!("E{:X} {:03X} {:04X}", fault.kind as u8, fault.pc, fault.ir);
+        let (width, _) = self.buffer_size;
+        let frame = &mut self.canvas;
+
+        for pixel in frame[..4 * width * 7].chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[0xaa, 0x00, 0x00, 0xff]);
+        }
+        draw_text(frame, width, 1, 1, &text);
+        self.fault_shown = true;
+        self.redraw = true;
+    }
+
+    // a row along the top while the beep sounds, as long as the sound timer has frames
+    // left up to a second's worth across the full width
+    fn draw_sound_bar(&mut self) {
+        self.sound_bar_shown = self.beeping;
+        if !self.beeping {
+            return;
+        }
+        let (width, _) = self.buffer_size;
+        let remaining = self.chip_8.snapshot().sound_timer as usize;
+        let len = (width * remaining / 60).clamp(1, width);
+        for pixel in self.canvas[..4 * len].chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[0xff, 0xa0, 0x20, 0xff]);
+        }
+        self.redraw = true;
+    }
+
+    // the font only has hex digits, so the banner along the bottom reads `<rom> <count>`,
+    // both counted from 1, with the name going to the window title instead
+    fn draw_rom_banner(&mut self) {
+        let text = format!("{:X} {:X}", self.rom_index + 1, self.roms.len());
+        let (width, height) = self.buffer_size;
+        let frame = &mut self.canvas;
+
+        let top = height.saturating_sub(7);
+        for pixel in frame[4 * width * top..4 * width * height].chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[0x20, 0x20, 0x40, 0xff]);
+        }
+        draw_text(frame, width, 1, top + 1, &text);
+        self.redraw = true;
+    }
+
+    // V0-VF four to a row, then `pc I delay sound`, over a dark box in the top left,
+    // with the quirks as hex digits of 4 bits each down the right in Quirks::NAMES order
+    fn draw_registers(&mut self) {
+        let registers = self.chip_8.snapshot();
+        let quirks = self.chip_8.quirks();
+        let quirk_bits = Quirks::NAMES
+            .iter()
+            .enumerate()
+            .filter(|&(_, name)| quirks.get(name).unwrap())
+            .fold(0u16, |bits, (n, _)| bits | 1 << n);
+        let mut lines: Vec<String> = registers
+            .v
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(n, v)| {
+                format!(
+                    "{:02X} {:02X} {:02X} {:02X} {:X}",
+                    v[0],
+                    v[1],
+                    v[2],
+                    v[3],
+                    quirk_bits >> (4 * n) & 0xF
+                )
+            })
+            .collect();
+        // the variant as 1 CHIP-8, 2 SUPER-CHIP or 3 XO-CHIP, like --quirk-test numbers them
+        lines.push(format!(
+            "{:03X} {:03X} {:02X} {:02X} {:X}",
+            registers.pc,
+            registers.i,
+            registers.delay_timer,
+            registers.sound_timer,
+            self.options.variant as u8 + 1
+        ));
+        let (width, _) = self.buffer_size;
+        let frame = &mut self.canvas;
+
+        let box_width = width.min(5 * 15);
+        for row in frame[..4 * width * (1 + 6 * lines.len())].chunks_exact_mut(4 * width) {
+            for pixel in row[..4 * box_width].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[0x20, 0x20, 0x40, 0xff]);
+            }
+        }
+        for (n, line) in lines.iter().enumerate() {
+            draw_text(frame, width, 0, 1 + 6 * n, line);
+        }
+    }
+
+    fn draw_sprite(&mut self, sprite: Sprite) {
+        let display = self.chip_8.display();
+        paint_sprite(
+            &mut self.canvas,
+            self.buffer_size,
+            display,
+            &self.palette,
+            &sprite,
+        );
+    }
+}
+
+fn hex_key(key_code: KeyCode) -> Option<usize> {
+    match key_code {
+        KeyCode::KeyX => Some(0),
+        KeyCode::Digit1 => Some(1),
+        KeyCode::Digit2 => Some(2),
+        KeyCode::Digit3 => Some(3),
+        KeyCode::KeyQ => Some(4),
+        KeyCode::KeyW => Some(5),
+        KeyCode::KeyE => Some(6),
+        KeyCode::KeyA => Some(7),
+        KeyCode::KeyS => Some(8),
+        KeyCode::KeyD => Some(9),
+        KeyCode::KeyZ => Some(0xA),
+        KeyCode::KeyC => Some(0xB),
+        KeyCode::Digit4 => Some(0xC),
+        KeyCode::KeyR => Some(0xD),
+        KeyCode::KeyF => Some(0xE),
+        KeyCode::KeyV => Some(0xF),
+        _ => None,
+    }
+}
+
+// EPX / Scale2x, each pixel becomes 2x2 and a corner takes a neighbour's color where
+// two neighbours agree on an edge running through it
+fn scale2x(canvas: &[u8], width: usize, frame: &mut [u8]) {
+    let height = canvas.len() / 4 / width;
+    let at = |x: usize, y: usize| &canvas[4 * (x + width * y)..4 * (x + width * y) + 4];
+    for y in 0..height {
+        for x in 0..width {
+            let p = at(x, y);
+            let a = at(x, y.saturating_sub(1));
+            let b = at((x + 1).min(width - 1), y);
+            let c = at(x.saturating_sub(1), y);
+            let d = at(x, (y + 1).min(height - 1));
+            let corners = [
+                if c == a && c != d && a != b { a } else { p },
+                if a == b && a != c && b != d { b } else { p },
+                if d == c && d != b && c != a { c } else { p },
+                if b == d && b != a && d != c { d } else { p },
+            ];
+            for (n, color) in corners.iter().enumerate() {
+                let index = 4 * (2 * x + n % 2 + 2 * width * (2 * y + n / 2));
+                frame[index..index + 4].copy_from_slice(color);
+            }
+        }
+    }
+}
+
+// bilinear interpolation between pixel centers, edges clamp
+fn upscale_linear(canvas: &[u8], width: usize, cell: usize, frame: &mut [u8]) {
+    let height = canvas.len() / 4 / width;
+    let at = |x: usize, y: usize| {
+        let i = 4 * (x + width * y);
+        [canvas[i], canvas[i + 1], canvas[i + 2], canvas[i + 3]]
+    };
+    for out_y in 0..cell * height {
+        let y = ((out_y as f32 + 0.5) / cell as f32 - 0.5).max(0.0);
+        let (y0, fy) = (y as usize, y.fract());
+        let y1 = (y0 + 1).min(height - 1);
+        for out_x in 0..cell * width {
+            let x = ((out_x as f32 + 0.5) / cell as f32 - 0.5).max(0.0);
+            let (x0, fx) = (x as usize, x.fract());
+            let x1 = (x0 + 1).min(width - 1);
+            let top = blend(at(x0, y0), at(x1, y0), fx);
+            let bottom = blend(at(x0, y1), at(x1, y1), fx);
+            let index = 4 * (out_x + cell * width * out_y);
+            frame[index..index + 4].copy_from_slice(&blend(top, bottom, fy));
+        }
+    }
+}
+
+fn blend(from: [u8; 4], to: [u8; 4], level: f32) -> [u8; 4] {
+    let mut color = from;
+    for (channel, (&from, &to)) in color.iter_mut().zip(from.iter().zip(&to)) {
+        *channel = (from as f32 + (to as f32 - from as f32) * level) as u8;
+    }
+    color
+}
+
+// keypad input on its way to the machine, releases waiting for the next loop (see
+// handle_keypad) and with --deterministic-input changes staged for the next frame, like
+// a replay applies them, so no instruction sees a key change mid frame and a recording
+// plays back the same way it was played
+#[derive(Default)]
+struct KeyInput {
+    deterministic: bool,
+    pending_releases: Vec<KeyCode>,
+    staged: Vec<(usize, ElementState)>, // held for the next frame
+}
+
+impl KeyInput {
+    // the change to make now, or None once it's staged for the next frame
+    fn send(&mut self, key: usize, state: ElementState) -> Option<(usize, ElementState)> {
+        if self.deterministic {
+            self.staged.push((key, state));
+            return None;
+        }
+        Some((key, state))
+    }
+
+    // releases made while unfocused never arrive and winit can't be asked what is held
+    // on the way back in, so every key in `key_state` counts as released when focus goes,
+    // returns the releases to make now
+    fn release_all(&mut self, key_state: u16) -> Vec<(usize, ElementState)> {
+        self.pending_releases.clear();
+        self.staged.clear();
+        held_keys(key_state)
+            .filter_map(|key| self.send(key, ElementState::Released))
+            .collect()
+    }
+}
+
+// the keys set in a key_state() bitmask
+fn held_keys(key_state: u16) -> impl Iterator<Item = usize> {
+    (0..16).filter(move |key| key_state & 1 << key != 0)
+}
+
+fn pixel_value(display: &[u8], pixel: usize) -> usize {
+    (display[pixel / 8] >> (7 - pixel % 8) & 1) as usize
+}
+
+// the core already xored the sprite in, so copy its pixels back from the display
+fn paint_sprite(
+    canvas: &mut [u8],
+    (width, height): (usize, usize),
+    display: &[u8],
+    palette: &Palette,
+    sprite: &Sprite,
+) {
+    let n_x = sprite.v_x & (width - 1);
+    let n_y = sprite.v_y & (height - 1);
+    for i in 0..sprite.rows().len() * sprite.scale {
+        for j in 0..8 * sprite.scale {
+            if (n_x + j) >= width {
+                continue;
+            }
+            if (n_y + i) >= height {
+                continue;
+            }
+            let pixel = n_x + j + width * (n_y + i);
+            let color = palette.color(pixel_value(display, pixel));
+            canvas[4 * pixel..4 * pixel + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+// white hex digits from the chip-8 font on a 5 pixel pitch, other characters leave a gap
+pub fn draw_text(frame: &mut [u8], width: usize, x: usize, y: usize, text: &str) {
+    for (n, c) in text.chars().enumerate() {
+        let Some(digit) = c.to_digit(16) else {
+            continue;
+        };
+        let glyph = &FONT[5 * digit as usize..5 * digit as usize + 5];
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..4 {
+                if x + 5 * n + col < width && bits & (0x80 >> col) != 0 {
+                    let index = 4 * (x + 5 * n + col + width * (y + row));
+                    frame[index..index + 4].copy_from_slice(&[0xff; 4]);
+                }
+            }
+        }
+    }
+}
+
+// owes at most MAX_CATCH_UP system ticks worth of cycles, like the timers
+fn cycle_clock(speed: f64) -> Clock {
+    let period = CYCLE_DURATION.div_f64(speed);
+    let max_ticks = (SYSTEM_DURATION * MAX_CATCH_UP).as_nanos() / period.as_nanos();
+    Clock::new(period, max_ticks as u32)
+}
+
+// advances `timer` by one `duration` so late ticks are made up instead of lost,
+// but snaps it to now after a long stall so it doesn't burst to catch up
+fn tick(timer: &mut Instant, duration: Duration) -> bool {
+    let elapsed = timer.elapsed();
+    if elapsed < duration {
+        return false;
+    }
+    if elapsed >= duration * MAX_CATCH_UP {
+        *timer = Instant::now();
+    } else {
+        *timer += duration;
+    }
+    true
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let window_attributes = Window::default_attributes().with_title("CHIP-8");
         let window = event_loop.create_window(window_attributes).unwrap();
-        let size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(size.width, size.height, &window);
-        let pixels = Pixels::new(WIDTH as u32, HEIGHT as u32, surface_texture).unwrap();
+        // present at the monitor's rate, the timers stay at 60 Hz either way
+        let monitor_millihertz = window
+            .current_monitor()
+            .and_then(|monitor| monitor.refresh_rate_millihertz())
+            .filter(|_| self.follow_monitor);
+        if let Some(millihertz) = monitor_millihertz {
+            self.refresh_duration = Duration::from_secs_f64(1000.0 / millihertz as f64);
+            log::info!(
+                "Refreshing at the monitor's {:.2} Hz",
+                millihertz as f64 / 1000.0
+            );
+        }
+        let (width, height) = self.chip_8.resolution();
+        self.buffer_size = (width, height);
+        let pixels = self.create_pixels(&window).unwrap();
+        self.canvas = vec![0; 4 * width * (height + self.keypad_height(width))];
         self.window = Some(window);
+        self.update_title();
         self.pixels = Some(pixels);
+        if self.onscreen_keypad {
+            self.draw_keypad();
+        }
+        self.repaint();
         let source = SignalGenerator::new(
             cpal::SampleRate(48000),
             220.0,
             rodio::source::Function::Triangle,
         );
-        self.sink.append(source);
-        self.sink.pause();
+        self.sink
+            .append(Envelope::new(source, self.beep_gate.clone()));
     }
 
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
-        _window_id: WindowId,
+        window_id: WindowId,
         event: WindowEvent,
     ) {
+        if self
+            .memory_view
+            .as_ref()
+            .is_some_and(|memory_view| memory_view.id() == window_id)
+        {
+            self.memory_view_event(event_loop, event);
+            return;
+        }
         match event {
             WindowEvent::Resized(size) => {
-                self.pixels
-                    .as_mut()
-                    .unwrap()
-                    .resize_surface(size.width, size.height)
-                    .unwrap();
+                // minimizing reports a zero sized window on some platforms, only the
+                // resize back from that undoes it, a window hidden behind others stays
+                // occluded until Occluded says otherwise
+                let minimized = size.width == 0 || size.height == 0;
+                if minimized != self.minimized {
+                    self.minimized = minimized;
+                    self.set_occluded(event_loop, minimized);
+                }
+                if minimized {
+                    log::debug!(
+                        "Skipping the resize to {}x{} while minimized",
+                        size.width,
+                        size.height
+                    );
+                    return;
+                }
+                let pixels = self.pixels.as_mut().unwrap();
+                if let Err(error) = pixels.resize_surface(size.width, size.height) {
+                    log::warn!(
+                        "Couldn't resize the surface to {}x{}: {error}",
+                        size.width,
+                        size.height
+                    );
+                }
             }
+            WindowEvent::Occluded(occluded) => self.set_occluded(event_loop, occluded),
+            WindowEvent::Focused(false) => self.release_all_keys(),
             WindowEvent::CloseRequested => {
                 event_loop.exit();
             }
@@ -199,9 +1674,117 @@ impl ApplicationHandler for App {
                     },
                 is_synthetic: false,
                 ..
-            } => self.chip_8.handle_input(key_code, state),
-            WindowEvent::RedrawRequested => self.main_loop(),
+            } => self.handle_window_key(event_loop, key_code, state),
+            WindowEvent::CursorMoved { position, .. } => self.cursor = position,
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } if self.onscreen_keypad => self.handle_click(state),
+            WindowEvent::RedrawRequested => self.main_loop(event_loop),
             _ => (),
         }
     }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        // silence the beep before the stream is torn down so closing mid beep doesn't
+        // leave a tone hanging, and get the last events and log lines out
+        self.beep_gate.store(false, Ordering::Relaxed);
+        self.sink.stop();
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.flush() {
+                log::error!("Failed to finish recording input: {}", err);
+            }
+        }
+        if let Some(path) = &self.screenshot_on_exit {
+            screenshot::save(self.chip_8.as_ref(), &self.palette, path);
+        }
+        if let Some(video) = &self.video {
+            video.save(&self.palette);
+        }
+        self.print_opcode_report();
+        log::info!("Shutting down");
+        log::logger().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_8::{HEIGHT, WIDTH};
+
+    #[test]
+    fn drawn_and_erased_pixels_stay_opaque() {
+        // the 0 glyph drawn, then drawn again over itself
+        let mut chip_8 = Chip8::from_rom(&[0xD0, 0x05, 0xD0, 0x05], Default::default());
+        let palette = Palette::default();
+        // a canvas nothing has painted yet, alpha included
+        let mut canvas = vec![0; 4 * WIDTH * HEIGHT];
+        for _ in 0..2 {
+            chip_8.instruction_cycle();
+            while let Some(job) = chip_8.poll_draw_queue() {
+                let DrawJob::Draw(sprite) = job else {
+                    continue;
+                };
+                paint_sprite(
+                    &mut canvas,
+                    (WIDTH, HEIGHT),
+                    chip_8.display(),
+                    &palette,
+                    &sprite,
+                );
+            }
+        }
+        for y in 0..5 {
+            for x in 0..8 {
+                let pixel = 4 * (x + WIDTH * y);
+                assert_eq!(canvas[pixel + 3], 0xff, "({x}, {y})");
+                assert_eq!(canvas[pixel..pixel + 4], palette.color(0), "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn focus_loss_releases_held_keys() {
+        use ElementState::{Pressed, Released};
+        for (deterministic, hold_taps) in [(false, false), (false, true), (true, false)] {
+            let mut options = Options::default();
+            options.quirks.hold_taps = hold_taps;
+            let mut chip_8 = Chip8::from_rom(&[0x12, 0x00], options);
+            let mut keys = KeyInput {
+                deterministic,
+                ..KeyInput::default()
+            };
+            let apply = |chip_8: &mut Chip8, changes: Vec<(usize, ElementState)>| {
+                for (key, state) in changes {
+                    chip_8.set_key(key, state.is_pressed());
+                }
+            };
+            for key in [3, 0xC] {
+                let now = keys.send(key, Pressed).into_iter().collect();
+                apply(&mut chip_8, now);
+            }
+            let staged = std::mem::take(&mut keys.staged);
+            apply(&mut chip_8, staged);
+            assert_eq!(held_keys(chip_8.key_state()).collect::<Vec<_>>(), [3, 0xC]);
+
+            // a release still waiting for the loop and a press staged for the next frame
+            keys.pending_releases.push(KeyCode::Digit1);
+            keys.send(5, Pressed);
+            let now = keys.release_all(chip_8.key_state());
+            assert!(keys.pending_releases.is_empty());
+            let releases = if deterministic {
+                assert!(now.is_empty());
+                std::mem::take(&mut keys.staged)
+            } else {
+                assert!(keys.staged.is_empty());
+                now
+            };
+            assert_eq!(releases, [(3, Released), (0xC, Released)]);
+            apply(&mut chip_8, releases);
+            // a tap held for polling roms lets go after the next instruction
+            chip_8.instruction_cycle();
+            assert_eq!(chip_8.key_state(), 0, "{deterministic} {hold_taps}");
+        }
+    }
 }
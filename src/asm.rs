@@ -0,0 +1,372 @@
+use std::{collections::HashMap, fmt::Display};
+
+const ENTRY: usize = 0x200;
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Clone)]
+enum Operand {
+    V(u16),
+    I,
+    IndirectI, // [I]
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Value(String), // number or label, resolved in the second pass
+}
+
+struct Statement {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+// every instruction both ways: its mnemonic, operands as written with `Vx` / `Vy` for
+// registers and `n` / `nn` / `nnn` for values, and its opcode with those fields zero,
+// searched in order so the fixed 00E0..00FF come before the SYS catching the rest
+const INSTRUCTIONS: [(&str, &str, u16); 38] = [
+    ("CLS", "", 0x00E0),
+    ("RET", "", 0x00EE),
+    ("EXIT", "", 0x00FD),
+    ("LOW", "", 0x00FE),
+    ("HIGH", "", 0x00FF),
+    ("SYS", "nnn", 0x0000),
+    ("JP", "nnn", 0x1000),
+    ("CALL", "nnn", 0x2000),
+    ("SE", "Vx, nn", 0x3000),
+    ("SNE", "Vx, nn", 0x4000),
+    ("SE", "Vx, Vy", 0x5000),
+    ("LD", "Vx, nn", 0x6000),
+    ("ADD", "Vx, nn", 0x7000),
+    ("LD", "Vx, Vy", 0x8000),
+    ("OR", "Vx, Vy", 0x8001),
+    ("AND", "Vx, Vy", 0x8002),
+    ("XOR", "Vx, Vy", 0x8003),
+    ("ADD", "Vx, Vy", 0x8004),
+    ("SUB", "Vx, Vy", 0x8005),
+    ("SHR", "Vx, Vy", 0x8006),
+    ("SUBN", "Vx, Vy", 0x8007),
+    ("SHL", "Vx, Vy", 0x800E),
+    ("SNE", "Vx, Vy", 0x9000),
+    ("LD", "I, nnn", 0xA000),
+    ("JP", "V0, nnn", 0xB000),
+    ("RND", "Vx, nn", 0xC000),
+    ("DRW", "Vx, Vy, n", 0xD000),
+    ("SKP", "Vx", 0xE09E),
+    ("SKNP", "Vx", 0xE0A1),
+    ("LD", "Vx, DT", 0xF007),
+    ("LD", "Vx, K", 0xF00A),
+    ("LD", "DT, Vx", 0xF015),
+    ("LD", "ST, Vx", 0xF018),
+    ("ADD", "I, Vx", 0xF01E),
+    ("LD", "F, Vx", 0xF029),
+    ("LD", "B, Vx", 0xF033),
+    ("LD", "[I], Vx", 0xF055),
+    ("LD", "Vx, [I]", 0xF065),
+];
+
+// Cowgod style mnemonics (`LD V0, 0x10`, `DRW V0, V1, 5`), `label:` definitions,
+// `db` for data bytes and `;` comments, rom bytes start at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    // first pass: split statements and give every label its address
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = ENTRY;
+    for (n, line) in source.lines().enumerate() {
+        let line_number = n + 1;
+        let mut line = line.split(';').next().unwrap().trim();
+        while let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                break;
+            }
+            if labels.insert(label.to_lowercase(), address).is_some() {
+                return Err(error(
+                    line_number,
+                    format!("label {label} is defined twice"),
+                ));
+            }
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mnemonic = mnemonic.to_uppercase();
+        let mut operands: Vec<Operand> = operands
+            .split(',')
+            .map(str::trim)
+            .filter(|operand| !operand.is_empty())
+            .map(parse_operand)
+            .collect();
+        // `SHR Vx` is shorthand for shifting a register in place
+        if matches!(mnemonic.as_str(), "SHR" | "SHL") && operands.len() == 1 {
+            operands.push(operands[0].clone());
+        }
+        address += if mnemonic == "DB" { operands.len() } else { 2 };
+        statements.push(Statement {
+            line: line_number,
+            mnemonic,
+            operands,
+        });
+    }
+
+    // second pass: encode with every label known
+    let mut rom = Vec::new();
+    for statement in &statements {
+        let line = statement.line;
+        let value = |operand: &Operand, max: u16| match operand {
+            Operand::Value(value) => {
+                let value = parse_number(value)
+                    .or_else(|| labels.get(&value.to_lowercase()).map(|&a| a as u16))
+                    .ok_or_else(|| error(line, format!("unknown label or number {value}")))?;
+                if value > max {
+                    return Err(error(line, format!("{value:#x} doesn't fit in {max:#x}")));
+                }
+                Ok(value)
+            }
+            _ => Err(error(
+                line,
+                format!("expected a value for {}", statement.mnemonic),
+            )),
+        };
+
+        if statement.mnemonic == "DB" {
+            for operand in &statement.operands {
+                rom.push(value(operand, 0xFF)? as u8);
+            }
+            continue;
+        }
+
+        let Some(&(_, template, base)) = INSTRUCTIONS.iter().find(|(mnemonic, template, _)| {
+            *mnemonic == statement.mnemonic && fits(template, &statement.operands)
+        }) else {
+            return Err(error(
+                line,
+                format!("can't assemble {} with these operands", statement.mnemonic),
+            ));
+        };
+        let mut opcode = base;
+        for (field, operand) in fields(template).zip(&statement.operands) {
+            opcode |= match (field, operand) {
+                ("Vx", Operand::V(x)) => x << 8,
+                ("Vy", Operand::V(y)) => y << 4,
+                ("n", value_operand) => value(value_operand, 0xF)?,
+                ("nn", value_operand) => value(value_operand, 0xFF)?,
+                ("nnn", value_operand) => value(value_operand, 0xFFF)?,
+                _ => 0,
+            };
+        }
+        rom.extend_from_slice(&opcode.to_be_bytes());
+    }
+    Ok(rom)
+}
+
+// the instruction in assembler syntax, None for opcodes no instruction has
+pub fn disassemble(opcode: u16) -> Option<String> {
+    let &(mnemonic, template, _) = INSTRUCTIONS
+        .iter()
+        .find(|&&(_, template, base)| opcode & !field_mask(template) == base)?;
+    let operands: Vec<_> = fields(template)
+        .map(|field| match field {
+            "Vx" => format!("V{:X}", opcode >> 8 & 0xF),
+            "Vy" => format!("V{:X}", opcode >> 4 & 0xF),
+            "n" => format!("{}", opcode & 0xF),
+            "nn" => format!("{:#04x}", opcode & 0xFF),
+            "nnn" => format!("{:#05x}", opcode & 0xFFF),
+            fixed => fixed.to_string(),
+        })
+        .collect();
+    if operands.is_empty() {
+        Some(mnemonic.to_string())
+    } else {
+        Some(format!("{} {}", mnemonic, operands.join(", ")))
+    }
+}
+
+fn fields(template: &str) -> impl Iterator<Item = &str> {
+    template.split(", ").filter(|field| !field.is_empty())
+}
+
+// the opcode bits the template's operands fill in
+fn field_mask(template: &str) -> u16 {
+    fields(template)
+        .map(|field| match field {
+            "Vx" => 0x0F00,
+            "Vy" => 0x00F0,
+            "n" => 0x000F,
+            "nn" => 0x00FF,
+            "nnn" => 0x0FFF,
+            _ => 0,
+        })
+        .fold(0, |mask, field| mask | field)
+}
+
+// whether the operands have the template's shape, values are checked when encoding
+fn fits(template: &str, operands: &[Operand]) -> bool {
+    let fields: Vec<_> = fields(template).collect();
+    fields.len() == operands.len()
+        && fields.iter().zip(operands).all(|(field, operand)| {
+            matches!(
+                (*field, operand),
+                ("Vx" | "Vy", Operand::V(_))
+                    | ("V0", Operand::V(0))
+                    | ("I", Operand::I)
+                    | ("[I]", Operand::IndirectI)
+                    | ("DT", Operand::Dt)
+                    | ("ST", Operand::St)
+                    | ("K", Operand::K)
+                    | ("F", Operand::F)
+                    | ("B", Operand::B)
+                    | ("n" | "nn" | "nnn", Operand::Value(_))
+            )
+        })
+}
+
+fn parse_operand(operand: &str) -> Operand {
+    match operand.to_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        upper => match upper
+            .strip_prefix('V')
+            .filter(|digit| digit.len() == 1)
+            .and_then(|digit| u16::from_str_radix(digit, 16).ok())
+        {
+            Some(x) => Operand::V(x),
+            None => Operand::Value(operand.to_string()),
+        },
+    }
+}
+
+fn parse_number(value: &str) -> Option<u16> {
+    let lower = value.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn error(line: usize, message: String) -> AsmError {
+    AsmError { line, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_every_mnemonic() {
+        let cases = [
+            ("CLS", 0x00E0),
+            ("RET", 0x00EE),
+            ("EXIT", 0x00FD),
+            ("LOW", 0x00FE),
+            ("HIGH", 0x00FF),
+            ("SYS 0x123", 0x0123),
+            ("JP V0, 0x345", 0xB345),
+            ("JP 0x345", 0x1345),
+            ("CALL 0x456", 0x2456),
+            ("SE V1, V2", 0x5120),
+            ("SE V1, 0x22", 0x3122),
+            ("SNE V1, V2", 0x9120),
+            ("SNE V1, 0x22", 0x4122),
+            ("LD V3, V4", 0x8340),
+            ("LD V3, DT", 0xF307),
+            ("LD V3, K", 0xF30A),
+            ("LD V3, [I]", 0xF365),
+            ("LD V3, 0x44", 0x6344),
+            ("LD I, 0x567", 0xA567),
+            ("LD DT, V5", 0xF515),
+            ("LD ST, V5", 0xF518),
+            ("LD F, V5", 0xF529),
+            ("LD B, V5", 0xF533),
+            ("LD [I], V5", 0xF555),
+            ("ADD I, V6", 0xF61E),
+            ("ADD V6, V7", 0x8674),
+            ("ADD V6, 1", 0x7601),
+            ("OR V8, V9", 0x8891),
+            ("AND V8, V9", 0x8892),
+            ("XOR V8, V9", 0x8893),
+            ("SUB V8, V9", 0x8895),
+            ("SHR VA", 0x8AA6),
+            ("SHR VA, VB", 0x8AB6),
+            ("SUBN V8, V9", 0x8897),
+            ("SHL VA", 0x8AAE),
+            ("SHL VA, VB", 0x8ABE),
+            ("RND VC, 0x0F", 0xCC0F),
+            ("DRW VD, VE, 5", 0xDDE5),
+            ("SKP VF", 0xEF9E),
+            ("SKNP VF", 0xEFA1),
+        ];
+        for (source, opcode) in cases {
+            let rom = assemble(source).unwrap();
+            assert_eq!(rom, u16::to_be_bytes(opcode), "{source}");
+        }
+    }
+
+    #[test]
+    fn resolves_labels_both_ways() {
+        let source = "start: CLS\n  ld i, sprite ; comment\nloop: JP loop\n CALL start\nsprite:";
+        let rom = assemble(source).unwrap();
+        assert_eq!(rom, [0x00, 0xE0, 0xA2, 0x08, 0x12, 0x04, 0x22, 0x00]);
+    }
+
+    #[test]
+    fn db_places_bytes_and_labels_after_it() {
+        let rom = assemble("CLS\ndata: db 0xF0, 0b1001, 144\nend: LD I, data\nJP end").unwrap();
+        assert_eq!(rom, [0x00, 0xE0, 0xF0, 0x09, 0x90, 0xA2, 0x02, 0x12, 0x05]);
+    }
+
+    #[test]
+    fn reports_the_failing_line() {
+        assert_eq!(assemble("CLS\nJP nowhere").unwrap_err().line, 2);
+        assert_eq!(assemble("LD V0, 0x100").unwrap_err().line, 1);
+        assert_eq!(assemble("a: CLS\na: CLS").unwrap_err().line, 2);
+        assert_eq!(assemble("DRW V0, 5").unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn round_trips_every_mnemonic() {
+        for &(mnemonic, template, base) in &INSTRUCTIONS {
+            // every field filled with distinct bits
+            let opcode = base | field_mask(template) & 0x0ABC;
+            let source = disassemble(opcode).unwrap();
+            assert!(source.starts_with(mnemonic), "{source}");
+            assert_eq!(assemble(&source).unwrap(), opcode.to_be_bytes(), "{source}");
+        }
+    }
+
+    #[test]
+    fn round_trips_every_opcode() {
+        for opcode in 0..=u16::MAX {
+            if let Some(source) = disassemble(opcode) {
+                assert_eq!(assemble(&source).unwrap(), opcode.to_be_bytes(), "{source}");
+            }
+        }
+        assert_eq!(disassemble(0x5121), None);
+        assert_eq!(disassemble(0xE0A2), None);
+        assert_eq!(disassemble(0xD125).unwrap(), "DRW V1, V2, 5");
+        assert_eq!(disassemble(0xB345).unwrap(), "JP V0, 0x345");
+    }
+}
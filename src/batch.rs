@@ -0,0 +1,97 @@
+use std::{any::Any, fs, panic, path::Path, process};
+
+use crate::{
+    chip_8::Chip8, chip_8_variant::Chip8Variant, config::Config, headless, options::Options,
+};
+
+pub const DEFAULT_CYCLES: u64 = 100_000;
+
+enum Outcome {
+    Ran,
+    Exited(u64), // cycle the rom stopped at without a fault
+    Faulted(String),
+    Panicked(String),
+    Unreadable(String),
+}
+
+// runs every .ch8 in a directory without a window, strict so unknown instructions
+// halt, and prints how each one ended, exiting with 1 if any faulted or panicked
+pub fn run(config: &Config, dir: &Path, cycles: u64) {
+    let mut roms: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "ch8"))
+            .collect(),
+        Err(err) => {
+            eprintln!("couldn't read {}: {err}", dir.display());
+            process::exit(1);
+        }
+    };
+    roms.sort();
+
+    let mut options = config.options.clone();
+    options.strict = true;
+    options.seed.get_or_insert(0); // repeatable runs for regression checks
+
+    let name_width = roms
+        .iter()
+        .map(|path| path.file_name().unwrap_or_default().len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    println!("{:name_width$}  result", "rom");
+    let mut counts = [0; 5];
+    for path in &roms {
+        let outcome = match fs::read(path) {
+            Ok(rom) => {
+                let options = options.clone();
+                panic::catch_unwind(|| run_rom(&rom, options, cycles))
+                    .unwrap_or_else(|payload| Outcome::Panicked(panic_message(payload)))
+            }
+            Err(err) => Outcome::Unreadable(err.to_string()),
+        };
+        let (index, result) = match outcome {
+            Outcome::Ran => (0, format!("ok ({cycles} cycles)")),
+            Outcome::Exited(cycle) => (1, format!("exited at cycle {cycle}")),
+            Outcome::Faulted(fault) => (2, format!("fault {fault}")),
+            Outcome::Panicked(message) => (3, format!("panicked: {message}")),
+            Outcome::Unreadable(err) => (4, format!("unreadable: {err}")),
+        };
+        counts[index] += 1;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        println!("{name:name_width$}  {result}");
+    }
+    let [ran, exited, faulted, panicked, unreadable] = counts;
+    println!(
+        "{} roms: {ran} ok, {exited} exited, {faulted} faulted, {panicked} panicked, {unreadable} unreadable",
+        roms.len()
+    );
+    if faulted + panicked > 0 {
+        process::exit(1);
+    }
+}
+
+fn run_rom(rom: &[u8], options: Options, cycles: u64) -> Outcome {
+    let mut chip_8 = Chip8::from_rom(rom, options);
+    for cycle in 0..cycles {
+        if let Some(fault) = chip_8.fault() {
+            return Outcome::Faulted(format!(
+                "{:?} at {:#05x} ({:#06x})",
+                fault.kind, fault.pc, fault.ir
+            ));
+        }
+        if chip_8.halted() {
+            return Outcome::Exited(cycle);
+        }
+        headless::step(&mut chip_8, cycle);
+    }
+    Outcome::Ran
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
@@ -0,0 +1,40 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    chip_8::Chip8, chip_8_variant::Chip8Variant, config::Config, headless, options::Options,
+};
+
+const RUNS: u32 = 5;
+const RUN_DURATION: Duration = Duration::from_secs(2);
+const BATCH_CYCLES: u64 = 1024; // cycles between clock reads
+
+pub fn run(config: &Config) {
+    let (_, rom) = config.first_rom();
+    let rates = (0..RUNS)
+        .map(|run| {
+            let rate = measure(&rom, &config.options);
+            println!("run {}: {:.0} cycles/s", run + 1, rate);
+            rate
+        })
+        .collect::<Vec<_>>();
+
+    let min = rates.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = rates.iter().copied().fold(0.0, f64::max);
+    let avg = rates.iter().sum::<f64>() / rates.len() as f64;
+    println!("min {:.0} / max {:.0} / avg {:.0} cycles/s", min, max, avg);
+}
+
+fn measure(rom: &[u8], options: &Options) -> f64 {
+    let mut chip_8 = Chip8::from_rom(rom, options.clone());
+    let start = Instant::now();
+
+    let mut cycle = 0;
+    while start.elapsed() < RUN_DURATION && !chip_8.halted() {
+        for _ in 0..BATCH_CYCLES {
+            headless::step(&mut chip_8, cycle);
+            cycle += 1;
+        }
+    }
+
+    chip_8.instruction_count() as f64 / start.elapsed().as_secs_f64()
+}
@@ -1,16 +1,34 @@
-use std::{collections::VecDeque, fs::File, io::prelude::*, path::Path, random::random};
-
-use winit::{event::ElementState, keyboard::KeyCode};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::Path,
+};
 
 use crate::{
-    chip_8_variant::Chip8Variant,
+    chip_8_variant::{Chip8Variant, Registers, WatchHit},
     draw_job::{DrawJob, Sprite},
+    extensions::Extensions,
+    fault::{Fault, FaultKind},
+    options::{Fill, Options, Watchpoint},
+    quirks::Quirks,
+    rng::Rng,
+    rom_info::RomInfo,
+    save_state::{StateError, StateReader, StateWriter},
+    system_event::SystemEvent,
+    timing,
 };
 
-const MEMORY_LENGTH: usize = 4096;
-const VRAM_LENGTH: usize = 256;
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+const VRAM_LENGTH: usize = HIRES_WIDTH * HIRES_HEIGHT / 8;
 const ENTRY: usize = 0x200;
-const FONT: [u8; 80] = [
+const GLYPH_SIZE: usize = 5;
+const MAX_DRAW_QUEUE: usize = 1024;
+const MAX_SYSTEM_EVENTS: usize = 16;
+const MAX_STACK_DEPTH: usize = 16; // SUPER-CHIP's, the VIP only had room for 12
+pub const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -31,124 +49,550 @@ const FONT: [u8; 80] = [
 
 #[derive(Debug)]
 pub struct Chip8 {
+    extensions: Extensions,
+    font_address: u16, // where FX29 finds the hex digit glyphs
     draw_queue: VecDeque<DrawJob>,
+    display_dirty: bool, // drawn to since the window last presented
+    system_events: VecDeque<SystemEvent>,
     stack: Vec<u16>,
     register_file: [u8; 16],
+    boot_register_file: [u8; 16], // registers as filled at power on, for resets
     ir: u16,
     pc: u16,
     indirect: u16,
     delay_timer: u8,
     sound_timer: u8,
-    memory: [u8; 4096],
-    video_memory: [u8; 256],
-    keyboard: [ElementState; 16],
-    key_latch: Option<u8>,
+    timers_frozen: bool, // debugging aid, holds both timers at their current value
+    memory: Vec<u8>,     // sized by the variant
+    boot_memory: Vec<u8>, // memory as loaded, for resets to go back to
+    video_memory: [u8; VRAM_LENGTH],
+    hires: bool,
+    keyboard: [bool; 16], // held keys
+    key_latches: u16,     // keys pressed since FX0A started waiting
     awaiting_key: bool,
+    tapped_keys: u16,
+    held_releases: u16,
+    menu_key: Option<u8>, // pressed for the first FX0A with --quirk-test
     instr: InstructionDecode,
+    opcode_counts: [u64; 16],
+    instruction_count: u64,
+    machine_cycles: u64,
+    options: Options,
+    halted: bool,
+    at_breakpoint: bool,
+    watch_hit: Option<WatchHit>,
+    fault: Option<Fault>,
+    rom_info: RomInfo,
+    rng: Rng,
 }
 
 impl Chip8 {
-    pub fn new<P>(path: P) -> Self
+    pub fn new<P>(path: P, options: Options) -> Self
     where
         P: AsRef<Path>,
     {
-        let mut memory = [0; MEMORY_LENGTH];
-        let mut file = File::open(path).unwrap();
-        memory[..80].copy_from_slice(&FONT);
-        let _ = file.read(&mut memory[ENTRY..]).unwrap();
-        memory[0x1FF] = 0; // quirk test specific
+        Self::from_rom(&fs::read(path).unwrap(), options)
+    }
+
+    pub fn from_rom(rom: &[u8], options: Options) -> Self {
+        let seed = options.seed.unwrap_or_else(Rng::time_seed);
+        log::info!("Random seed: {}", seed);
+        let memory_size = options.extensions().memory_size();
+        let mut memory = vec![0; memory_size];
+        let mut register_file = [0; 16];
+        // the VIP never clears RAM at power-on and keeps V0-VF in RAM at 0xEF0, so both
+        // start out as noise, drawn from its own stream to leave CXNN untouched, other fills
+        // flush out roms that read memory or registers they never wrote
+        let fill = if options.vip_boot {
+            Fill::Random
+        } else {
+            options.fill
+        };
+        match fill {
+            Fill::Zero => (),
+            Fill::Ff => {
+                memory.fill(0xFF);
+                register_file.fill(0xFF);
+            }
+            Fill::Random => {
+                let mut noise = Rng::new(seed.rotate_left(32));
+                memory.fill_with(|| noise.next_u8());
+                register_file.fill_with(|| noise.next_u8());
+            }
+            Fill::Pattern => {
+                for (address, byte) in memory.iter_mut().enumerate() {
+                    *byte = address as u8;
+                }
+                for (x, register) in register_file.iter_mut().enumerate() {
+                    *register = x as u8;
+                }
+            }
+        }
+        // whole memory dumps bring their own font and are too long to sit at 0x200,
+        // they load from 0x000 but still start running at 0x200
+        let raw_image =
+            options.raw_image || rom.starts_with(&FONT) || rom.len() > memory_size - ENTRY;
+        let rom_len = if raw_image {
+            let len = rom.len().min(memory_size);
+            memory[..len].copy_from_slice(&rom[..len]);
+            len.saturating_sub(ENTRY)
+        } else {
+            memory[..FONT.len()].copy_from_slice(&FONT);
+            memory[ENTRY..ENTRY + rom.len()].copy_from_slice(rom);
+            rom.len()
+        };
+        let rom_info = RomInfo::inspect(&memory[ENTRY..ENTRY + rom_len]);
+        log::info!(
+            "Loaded rom as a {}: {}",
+            if raw_image {
+                "raw memory image"
+            } else {
+                "program at 0x200"
+            },
+            rom_info
+        );
+        Self::boot(memory, register_file, rom_info, seed, options)
+    }
+
+    // a whole memory image with no file behind it, for fuzzing the executor, cut or
+    // zero padded to the variant's memory size
+    pub fn with_memory(mut memory: Vec<u8>, options: Options) -> Self {
+        memory.resize(options.extensions().memory_size(), 0);
+        let seed = options.seed.unwrap_or_else(Rng::time_seed);
+        let rom_info = RomInfo::inspect(&memory[ENTRY..]);
+        Self::boot(memory, [0; 16], rom_info, seed, options)
+    }
+
+    fn boot(
+        memory: Vec<u8>,
+        register_file: [u8; 16],
+        rom_info: RomInfo,
+        seed: u64,
+        options: Options,
+    ) -> Self {
+        // memory dumps from interpreters that keep the font elsewhere below 0x200 (0x050
+        // is common) still find it, custom fonts can't be recognised and are taken to
+        // start at 0x000 like ours
+        let font_address = memory[..ENTRY]
+            .windows(FONT.len())
+            .position(|window| window == FONT)
+            .unwrap_or(0);
+        if font_address != 0 {
+            log::info!("Found the font at {:#05X}", font_address);
+        }
+        let extensions = options.extensions();
+        log::info!("Extensions: {}", extensions);
         Self {
+            extensions,
+            font_address: font_address as u16,
             draw_queue: VecDeque::new(),
+            display_dirty: true,
+            system_events: VecDeque::new(),
             stack: Vec::new(),
-            register_file: [0; 16],
+            register_file,
+            boot_register_file: register_file,
             ir: 0,
             pc: ENTRY as u16,
             indirect: 0,
             delay_timer: 0,
             sound_timer: 0,
+            timers_frozen: false,
+            boot_memory: memory.clone(),
             memory,
             video_memory: [0; VRAM_LENGTH],
-            keyboard: [ElementState::Released; 16],
-            key_latch: None,
+            hires: false,
+            keyboard: [false; 16],
+            key_latches: 0,
             awaiting_key: false,
+            tapped_keys: 0,
+            held_releases: 0,
+            menu_key: options.quirk_test,
             instr: InstructionDecode::decode(0),
+            opcode_counts: [0; 16],
+            instruction_count: 0,
+            machine_cycles: 0,
+            options,
+            halted: false,
+            at_breakpoint: false,
+            watch_hit: None,
+            fault: None,
+            rom_info,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Chip8 {
+    pub fn rom_info(&self) -> &RomInfo {
+        &self.rom_info
+    }
+
+    fn watched_value(&self, watchpoint: Watchpoint) -> u16 {
+        match watchpoint {
+            Watchpoint::V(x) => self.register_file[x] as u16,
+            Watchpoint::I => self.indirect,
+            Watchpoint::Memory(address) => {
+                self.memory.get(address as usize).map_or(0, |&b| b as u16)
+            }
         }
     }
 }
 
 impl Chip8Variant for Chip8 {
     fn instruction_cycle(&mut self) {
+        if self.halted {
+            return;
+        }
+        self.watch_hit = None;
+        // a breakpoint stops the machine once, the next cycle runs the instruction under it
+        if !std::mem::take(&mut self.at_breakpoint) && self.options.breakpoints.contains(&self.pc) {
+            self.at_breakpoint = true;
+            return;
+        }
+        let pc = self.pc;
+        let watched: Vec<_> = self
+            .options
+            .watchpoints
+            .iter()
+            .map(|&watchpoint| (watchpoint, self.watched_value(watchpoint)))
+            .collect();
         self.fetch();
+        if self.halted {
+            return;
+        }
         self.decode();
         self.execute();
+        self.watch_hit = watched.into_iter().find_map(|(watchpoint, old)| {
+            let new = self.watched_value(watchpoint);
+            (new != old).then_some(WatchHit {
+                watchpoint,
+                old,
+                new,
+                pc,
+                ir: self.ir,
+            })
+        });
+        self.instruction_count += 1;
+        self.machine_cycles += timing::vip_cycles(self.ir) as u64;
+
+        self.tapped_keys = 0;
+        let releases = std::mem::take(&mut self.held_releases);
+        for key in (0..16).filter(|key| releases & 1 << key != 0) {
+            self.set_key(key, false);
+        }
     }
 
     fn decrement_timers(&mut self) {
+        if self.timers_frozen {
+            return;
+        }
         self.delay_timer = self.delay_timer.saturating_sub(1);
         self.sound_timer = self.sound_timer.saturating_sub(1);
     }
 
-    fn handle_input(&mut self, key_code: KeyCode, state: ElementState) {
-        if let Some(key) = match key_code {
-            KeyCode::KeyX => Some(0),
-            KeyCode::Digit1 => Some(1),
-            KeyCode::Digit2 => Some(2),
-            KeyCode::Digit3 => Some(3),
-            KeyCode::KeyQ => Some(4),
-            KeyCode::KeyW => Some(5),
-            KeyCode::KeyE => Some(6),
-            KeyCode::KeyA => Some(7),
-            KeyCode::KeyS => Some(8),
-            KeyCode::KeyD => Some(9),
-            KeyCode::KeyZ => Some(0xA),
-            KeyCode::KeyC => Some(0xB),
-            KeyCode::Digit4 => Some(0xC),
-            KeyCode::KeyR => Some(0xD),
-            KeyCode::KeyF => Some(0xE),
-            KeyCode::KeyV => Some(0xF),
-            _ => None,
-        } {
-            self.keyboard[key] = state;
-            if self.awaiting_key {
-                match self.key_latch {
-                    Some(key_latch) => {
-                        if key_latch == key as u8 {
-                            self.register_file[self.instr.x] = key_latch;
-                            self.awaiting_key = false;
-                            self.key_latch = None;
-                        }
-                    }
-                    None => self.key_latch = Some(key as u8),
-                }
+    fn key_state(&self) -> u16 {
+        self.keyboard
+            .iter()
+            .enumerate()
+            .filter(|(_, &pressed)| pressed)
+            .fold(0, |bits, (key, _)| bits | 1 << key)
+    }
+
+    fn timers_frozen(&self) -> bool {
+        self.timers_frozen
+    }
+
+    fn set_timers_frozen(&mut self, frozen: bool) {
+        self.timers_frozen = frozen;
+    }
+
+    fn set_key(&mut self, key: usize, pressed: bool) {
+        // FX0A already waits for the release, so only hold keys for polling roms
+        if self.options.quirks.hold_taps && !self.awaiting_key {
+            if pressed {
+                self.tapped_keys |= 1 << key;
+                self.held_releases &= !(1 << key);
+            } else if self.tapped_keys & 1 << key != 0 {
+                self.held_releases |= 1 << key;
+                return;
+            }
+        }
+        self.keyboard[key] = pressed;
+        // FX0A resolves on the first key to be both pressed and released while it waits,
+        // overlapping presses are all latched and keys held from before are ignored
+        if self.awaiting_key {
+            if pressed {
+                self.key_latches |= 1 << key;
+            } else if self.key_latches & 1 << key != 0 {
+                self.register_file[self.instr.x] = key as u8;
+                self.awaiting_key = false;
+                self.key_latches = 0;
             }
         }
     }
 
-    fn sound_timer(&self) -> u8 {
-        self.sound_timer
+    fn sound_active(&self) -> bool {
+        let threshold = if self.options.quirks.vip_buzzer { 1 } else { 0 };
+        self.sound_timer > threshold
     }
 
     fn waiting(&self) -> bool {
         self.awaiting_key
     }
 
+    // `loop: FX07, 3X00, 1loop` can only leave once the delay timer reaches 0, so with
+    // pc at its top and the timer above 0 nothing changes before the next tick
+    fn idling(&self) -> bool {
+        let pc = self.pc as usize;
+        let Some(code) = self.memory.get(pc..pc + 6) else {
+            return false;
+        };
+        let x = code[0] & 0xF;
+        let jump = u16::from_be_bytes([code[4], code[5]]);
+        self.delay_timer > 0
+            && code[..4] == [0xF0 | x, 0x07, 0x30 | x, 0x00]
+            && jump == 0x1000 | self.pc
+    }
+
+    fn halted(&self) -> bool {
+        self.halted
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        self.at_breakpoint
+    }
+
+    fn breakpoints(&self) -> &HashSet<u16> {
+        &self.options.breakpoints
+    }
+
+    fn toggle_breakpoint(&mut self, address: u16) -> bool {
+        let breakpoints = &mut self.options.breakpoints;
+        !breakpoints.remove(&address) && breakpoints.insert(address)
+    }
+
+    fn poke_memory(&mut self, address: u16, value: u8) -> Option<u8> {
+        let byte = self.memory.get_mut(address as usize)?;
+        Some(std::mem::replace(byte, value))
+    }
+
+    fn set_register(&mut self, x: usize, value: u8) -> Option<u8> {
+        let register = self.register_file.get_mut(x)?;
+        Some(std::mem::replace(register, value))
+    }
+
+    fn watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit
+    }
+
+    fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
     fn poll_draw_queue(&mut self) -> Option<DrawJob> {
         self.draw_queue.pop_front()
     }
 
-    fn set_collision(&mut self, collides: bool) {
-        self.register_file[0xF] = if collides { 1 } else { 0 }
+    fn poll_system_event(&mut self) -> Option<SystemEvent> {
+        self.system_events.pop_front()
+    }
+
+    fn display(&self) -> &[u8] {
+        &self.video_memory
+    }
+
+    fn display_dirty(&self) -> bool {
+        self.display_dirty
+    }
+
+    fn mark_display_clean(&mut self) {
+        self.display_dirty = false;
+    }
+
+    fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    // SUPER-CHIP keeps a 128x64 display in both modes and doubles lores pixels into it
+    fn resolution(&self) -> (usize, usize) {
+        if self.extensions.schip_hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (WIDTH, HEIGHT)
+        }
+    }
+
+    fn opcode_counts(&self) -> [u64; 16] {
+        self.opcode_counts
+    }
+
+    fn quirks(&self) -> &Quirks {
+        &self.options.quirks
+    }
+
+    fn set_quirk(&mut self, name: &str, value: bool) -> bool {
+        self.options.quirks.set(name, value)
+    }
+
+    fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    fn machine_cycles(&self) -> u64 {
+        self.machine_cycles
+    }
+
+    fn snapshot(&self) -> Registers {
+        Registers {
+            v: self.register_file,
+            i: self.indirect,
+            pc: self.pc,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u32(self.rom_info.crc32);
+        writer.u16(self.stack.len() as u16);
+        for &addr in &self.stack {
+            writer.u16(addr);
+        }
+        writer.bytes(&self.register_file);
+        writer.u16(self.ir);
+        writer.u16(self.pc);
+        writer.u16(self.indirect);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.u32(self.memory.len() as u32);
+        writer.bytes(&self.memory);
+        writer.bytes(&self.video_memory);
+        writer.u8(self.hires as u8);
+        writer.u16(self.key_latches);
+        writer.u8(self.awaiting_key as u8);
+        writer.u8(self.halted as u8);
+        let fault = self.fault.map_or((0, 0, 0), |f| (f.kind as u8, f.pc, f.ir));
+        writer.u8(fault.0);
+        writer.u16(fault.1);
+        writer.u16(fault.2);
+        writer.u64(self.rng.state());
+        writer.finish()
+    }
+
+    // power cycle without reloading the rom, memory goes back to how it was loaded
+    // while quirks, frozen timers and the opcode stats carry over
+    fn reset(&mut self) {
+        let was_hires = self.hires;
+        self.draw_queue.clear();
+        self.draw_queue.push_back(DrawJob::Repaint);
+        self.display_dirty = true;
+        self.system_events.clear();
+        self.stack.clear();
+        self.register_file = self.boot_register_file;
+        self.ir = 0;
+        self.pc = ENTRY as u16;
+        self.indirect = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.memory.copy_from_slice(&self.boot_memory);
+        self.video_memory = [0; VRAM_LENGTH];
+        self.hires = false;
+        self.keyboard = [false; 16];
+        self.key_latches = 0;
+        self.awaiting_key = false;
+        self.tapped_keys = 0;
+        self.held_releases = 0;
+        self.menu_key = self.options.quirk_test;
+        self.instr = InstructionDecode::decode(0);
+        self.halted = false;
+        self.at_breakpoint = false;
+        self.watch_hit = None;
+        self.fault = None;
+        if was_hires {
+            self.signal(SystemEvent::ResolutionChanged { hires: false });
+        }
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state)?;
+        let rom_hash = reader.u32()?;
+        if rom_hash != self.rom_info.crc32 {
+            log::warn!(
+                "State was saved from rom {:08x} but {:08x} is loaded",
+                rom_hash,
+                self.rom_info.crc32
+            );
+        }
+        let stack_len = reader.u16()?;
+        let stack = (0..stack_len)
+            .map(|_| reader.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+        let register_file = reader.bytes(16)?.try_into().unwrap();
+        let ir = reader.u16()?;
+        let pc = reader.u16()?;
+        let indirect = reader.u16()?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let memory_size = reader.u32()? as usize;
+        if memory_size != self.memory.len() {
+            return Err(StateError::MemorySize(memory_size));
+        }
+        let memory = reader.bytes(memory_size)?.to_vec();
+        let video_memory = reader.bytes(VRAM_LENGTH)?.try_into().unwrap();
+        let hires = reader.u8()? != 0;
+        let key_latches = reader.u16()?;
+        let awaiting_key = reader.u8()? != 0;
+        let halted = reader.u8()? != 0;
+        let fault_kind = reader.u8()?;
+        let fault_pc = reader.u16()?;
+        let fault_ir = reader.u16()?;
+        let fault = FaultKind::from_u8(fault_kind).map(|kind| Fault {
+            kind,
+            pc: fault_pc,
+            ir: fault_ir,
+        });
+        let rng = Rng::from_state(reader.u64()?);
+
+        self.draw_queue.clear();
+        self.display_dirty = true;
+        self.system_events.clear();
+        self.stack = stack;
+        self.register_file = register_file;
+        self.ir = ir;
+        self.pc = pc;
+        self.indirect = indirect;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.memory = memory;
+        self.video_memory = video_memory;
+        self.hires = hires;
+        self.key_latches = key_latches;
+        self.awaiting_key = awaiting_key;
+        self.halted = halted;
+        self.at_breakpoint = false;
+        self.watch_hit = None;
+        self.fault = fault;
+        self.rng = rng;
+        self.instr = InstructionDecode::decode(ir);
+        Ok(())
     }
 }
 
 impl Chip8 {
     fn fetch(&mut self) {
-        self.ir = u16::from_be_bytes(
-            self.memory[self.pc as usize..self.pc as usize + 2]
-                .try_into()
-                .unwrap(),
-        );
-        self.pc += 2;
+        self.pc = self.wrap_address(self.pc as usize) as u16;
+        let next = self.wrap_address(self.pc as usize + 1);
+        if next >= self.memory.len() {
+            self.fault(FaultKind::OutOfBounds, self.pc);
+            return;
+        }
+        self.ir = u16::from_be_bytes([self.memory[self.pc as usize], self.memory[next]]);
+        self.pc = self.pc.wrapping_add(2);
     }
 
     fn decode(&mut self) {
@@ -156,17 +600,28 @@ impl Chip8 {
     }
 
     fn execute(&mut self) {
+        self.opcode_counts[self.instr.opcode as usize] += 1;
         match self.instr.opcode {
             0x0 => match self.instr.address {
                 0x0E0 => self.clear_screen(),
                 0x0EE => self.ret(),
-                _ => log::error!("Unknown instruction {:#06x}", self.ir),
+                0x0FD if self.extensions.schip_exit => self.exit(),
+                0x0FE if self.extensions.schip_hires => self.set_hires(false),
+                0x0FF if self.extensions.schip_hires => self.set_hires(true),
+                _ => self.sys(self.instr.address),
             },
             0x1 => self.jump(self.instr.address),
             0x2 => self.call(self.instr.address),
             0x3 => self.skip_vx_e_imm(self.instr.x, self.instr.immediate),
             0x4 => self.skip_vx_ne_imm(self.instr.x, self.instr.immediate),
-            0x5 => self.skip_vx_e_vy(self.instr.x, self.instr.y),
+            0x5 => match self.instr.funct {
+                0x2 if self.extensions.xochip_ranges => {
+                    self.store_range(self.instr.x, self.instr.y)
+                }
+                0x3 if self.extensions.xochip_ranges => self.load_range(self.instr.x, self.instr.y),
+                0x0 => self.skip_vx_e_vy(self.instr.x, self.instr.y),
+                _ => self.unknown_instruction(),
+            },
             0x6 => self.load_imm(self.instr.x, self.instr.immediate),
             0x7 => self.add_imm(self.instr.x, self.instr.immediate),
             0x8 => match self.instr.funct {
@@ -179,68 +634,222 @@ impl Chip8 {
                 0x6 => self.shr_reg(self.instr.x, self.instr.y),
                 0x7 => self.subn_reg(self.instr.x, self.instr.y),
                 0xE => self.shl_reg(self.instr.x, self.instr.y),
-                _ => log::error!("Unknown instruction {:#06x}", self.ir),
+                _ => self.unknown_instruction(),
+            },
+            0x9 => match self.instr.funct {
+                0x0 => self.skip_vx_ne_vy(self.instr.x, self.instr.y),
+                _ => self.unknown_instruction(),
             },
-            0x9 => self.skip_vx_ne_vy(self.instr.x, self.instr.y),
             0xA => self.load_addr(self.instr.address),
-            0xB => self.pc = self.instr.address + self.register_file[0] as u16,
-            0xC => self.register_file[self.instr.x] = random::<u8>() & self.instr.immediate,
+            0xB => self.jump(self.instr.address + self.register_file[0] as u16),
+            0xC => self.register_file[self.instr.x] = self.rng.next_u8() & self.instr.immediate,
             0xD => self.draw_sprite(self.instr.x, self.instr.y, self.instr.funct),
             0xE => match self.instr.immediate {
                 0x9E => self.skip_pressed(self.instr.x),
                 0xA1 => self.skip_not_pressed(self.instr.x),
-                _ => log::error!("Unknown instruction {:#06x}", self.ir),
+                _ => self.unknown_instruction(),
             },
             0xF => match self.instr.immediate {
-                0x07 => self.register_file[self.instr.x] = self.delay_timer,
+                0x07 => self.read_delay_timer(self.instr.x),
                 0x0A => self.get_key(self.instr.x),
-                0x15 => self.delay_timer = self.register_file[self.instr.x],
+                0x15 => self.load_delay_timer(self.instr.x),
                 0x18 => self.load_sound_timer(self.instr.x),
-                0x1E => self.indirect += self.register_file[self.instr.x] as u16,
+                0x1E => self.add_indirect(self.register_file[self.instr.x] as usize),
                 0x29 => self.load_hex_sprite(self.instr.x),
                 0x33 => self.store_bcd(self.instr.x),
                 0x55 => self.store_block(self.instr.x),
                 0x65 => self.load_block(self.instr.x),
-                _ => log::error!("Unknown instruction {:#06x}", self.ir),
+                _ => self.unknown_instruction(),
             },
-            _ => log::error!("Unknown instruction {:#06x}", self.ir),
+            _ => self.unknown_instruction(),
         }
     }
 }
 
 impl Chip8 {
+    // where the instruction being executed sits, pc having already moved past it, wrapping
+    // back from 0 like skip() wraps forward
+    fn instruction_address(&self) -> u16 {
+        self.pc.wrapping_sub(2)
+    }
+
+    fn unknown_instruction(&mut self) {
+        let pc = self.instruction_address();
+        if self.options.strict {
+            self.fault(FaultKind::UnknownInstruction, pc);
+        } else {
+            log::error!("Unknown instruction {:#06x} at {:#05x}", self.ir, pc);
+        }
+    }
+
+    fn fault(&mut self, kind: FaultKind, pc: u16) {
+        log::error!("Halting on {:?} at {:#05x} ({:#06x})", kind, pc, self.ir);
+        let fault = Fault {
+            kind,
+            pc,
+            ir: self.ir,
+        };
+        self.fault = Some(fault);
+        self.halted = true;
+        self.signal(SystemEvent::Fault(fault));
+    }
+
+    fn signal(&mut self, event: SystemEvent) {
+        if self.system_events.len() >= MAX_SYSTEM_EVENTS {
+            self.system_events.pop_front();
+        }
+        self.system_events.push_back(event);
+    }
+
+    // with wrap_addresses an address keeps only the bits the memory size covers, 12 on
+    // CHIP-8 and 16 on XO-CHIP, without it addresses run on and fault past the end
+    fn wrap_address(&self, address: usize) -> usize {
+        if self.options.quirks.wrap_addresses {
+            address & (self.memory.len() - 1)
+        } else {
+            address
+        }
+    }
+
+    // addresses of `len` bytes at `I`, faulting instead of reading past the end of memory
+    // unless they wrap
+    fn indirect_addresses(&mut self, len: usize) -> Option<impl Iterator<Item = usize>> {
+        let start = self.indirect as usize;
+        if !self.options.quirks.wrap_addresses && start + len > self.memory.len() {
+            self.fault(FaultKind::OutOfBounds, self.instruction_address());
+            return None;
+        }
+        let mask = self.memory.len() - 1;
+        Some((start..start + len).map(move |address| address & mask))
+    }
+
+    // the font and interpreter area below 0x200 is no place for a rom to write, warned
+    // about under --strict like jumps there, without halting
+    fn check_reserved_write(&self, len: usize) {
+        if !self.options.strict {
+            return;
+        }
+        let mask = self.memory.len() - 1;
+        let start = self.indirect as usize;
+        if (start..start + len).any(|address| address & mask < ENTRY) {
+            log::warn!(
+                "Write to reserved memory at {:#05x} by {:#06x} at {:#05x}",
+                self.indirect,
+                self.ir,
+                self.instruction_address()
+            );
+        }
+    }
+
+    fn add_indirect(&mut self, n: usize) {
+        self.indirect = self.wrap_address(self.indirect as usize + n) as u16;
+    }
+
+    // pc is a 16 bit register, it wraps rather than overflows
+    fn skip(&mut self) {
+        self.pc = self.pc.wrapping_add(2);
+    }
+
     fn clear_screen(&mut self) {
-        self.draw_queue.push_back(DrawJob::Clear);
+        self.video_memory = [0; VRAM_LENGTH];
+        self.queue_draw(DrawJob::Clear);
+    }
+
+    // the display is hires either way, so the picture stays put unless the quirk clears it
+    fn set_hires(&mut self, hires: bool) {
+        if self.options.quirks.clear_on_res_switch {
+            self.video_memory = [0; VRAM_LENGTH];
+            self.queue_draw(DrawJob::Clear);
+        }
+        if self.hires != hires {
+            self.hires = hires;
+            self.signal(SystemEvent::ResolutionChanged { hires });
+        }
+    }
+
+    // nothing polls the queue while the window is minimized, so past the cap it collapses
+    // to a repaint from the display buffer, which already holds what the jobs would draw
+    fn queue_draw(&mut self, job: DrawJob) {
+        if self.draw_queue.len() >= MAX_DRAW_QUEUE {
+            log::debug!(
+                "Draw queue full, dropping {} jobs for a repaint",
+                self.draw_queue.len()
+            );
+            self.draw_queue.clear();
+            self.draw_queue.push_back(DrawJob::Repaint);
+        }
+        self.draw_queue.push_back(job);
+        self.display_dirty = true;
+    }
+
+    // display pixels per sprite pixel, lores on SUPER-CHIP covers 2x2 blocks
+    fn pixel_scale(&self) -> usize {
+        if self.extensions.schip_hires && !self.hires {
+            2
+        } else {
+            1
+        }
     }
 
     fn ret(&mut self) {
-        self.pc = self.stack.pop().unwrap();
+        match self.stack.pop() {
+            Some(addr) => self.set_pc(addr),
+            None => self.fault(FaultKind::StackUnderflow, self.instruction_address()),
+        }
+    }
+
+    fn sys(&mut self, addr: u16) {
+        // machine code routines only existed on the original hardware
+        log::debug!("Ignoring SYS {:#05x}", addr);
+    }
+
+    // the rom is done, the machine stops like it does on a fault but without one
+    fn exit(&mut self) {
+        log::info!("Exited at {:#05x}", self.instruction_address());
+        self.halted = true;
+        self.signal(SystemEvent::Exit);
     }
 
     fn jump(&mut self, addr: u16) {
-        self.pc = addr;
+        self.set_pc(addr);
     }
 
     fn call(&mut self, addr: u16) {
+        if self.stack.len() >= MAX_STACK_DEPTH {
+            self.fault(FaultKind::StackOverflow, self.instruction_address());
+            return;
+        }
         self.stack.push(self.pc);
+        self.set_pc(addr);
+    }
+
+    fn set_pc(&mut self, addr: u16) {
+        if self.options.strict && (addr & 1 != 0 || addr < ENTRY as u16) {
+            log::warn!(
+                "Suspicious jump to {:#05x} by {:#06x} at {:#05x}",
+                addr,
+                self.ir,
+                self.instruction_address()
+            );
+        }
         self.pc = addr;
     }
 
     fn skip_vx_e_imm(&mut self, x: usize, imm: u8) {
         if self.register_file[x] == imm {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn skip_vx_ne_imm(&mut self, x: usize, imm: u8) {
         if self.register_file[x] != imm {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn skip_vx_e_vy(&mut self, x: usize, y: usize) {
         if self.register_file[x] == self.register_file[y] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -252,19 +861,27 @@ impl Chip8 {
         self.register_file[x] = self.register_file[x].wrapping_add(imm);
     }
 
+    // the 8XYN ops write Vx before VF, so with x == F the flag overwrites the result
+    // like on the VIP, shifts read Vy up front in case y == F
     fn or_reg(&mut self, x: usize, y: usize) {
         self.register_file[x] |= self.register_file[y];
-        self.register_file[0xF] = 0;
+        self.reset_vf();
     }
 
     fn and_reg(&mut self, x: usize, y: usize) {
         self.register_file[x] &= self.register_file[y];
-        self.register_file[0xF] = 0;
+        self.reset_vf();
     }
 
     fn xor_reg(&mut self, x: usize, y: usize) {
         self.register_file[x] ^= self.register_file[y];
-        self.register_file[0xF] = 0;
+        self.reset_vf();
+    }
+
+    fn reset_vf(&mut self) {
+        if self.options.quirks.vf_reset {
+            self.register_file[0xF] = 0;
+        }
     }
 
     fn add_reg(&mut self, x: usize, y: usize) {
@@ -280,56 +897,110 @@ impl Chip8 {
     }
 
     fn shr_reg(&mut self, x: usize, y: usize) {
-        let v_y = self.register_file[y];
-        self.register_file[x] = self.register_file[y].wrapping_shr(1);
-        self.register_file[0xF] = v_y & 1;
+        let source = self.register_file[self.shift_source(x, y)];
+        self.register_file[x] = source >> 1;
+        self.register_file[0xF] = source & 1;
     }
 
     fn subn_reg(&mut self, x: usize, y: usize) {
-        let (result, carry) = self.register_file[y].overflowing_sub(self.register_file[x]);
+        let (result, borrow) = self.register_file[y].overflowing_sub(self.register_file[x]);
         self.register_file[x] = result;
-        self.register_file[0xF] = if carry { 0 } else { 1 };
+        self.register_file[0xF] = if borrow { 0 } else { 1 };
     }
 
     fn shl_reg(&mut self, x: usize, y: usize) {
-        let v_y = self.register_file[y];
-        self.register_file[x] = self.register_file[y].wrapping_shl(1);
-        self.register_file[0xF] = v_y >> 7;
+        let source = self.register_file[self.shift_source(x, y)];
+        self.register_file[x] = source << 1;
+        self.register_file[0xF] = source >> 7;
+    }
+
+    fn shift_source(&self, x: usize, y: usize) -> usize {
+        if self.options.quirks.shift_vx {
+            x
+        } else {
+            y
+        }
     }
 
     fn load_addr(&mut self, addr: u16) {
-        self.indirect = addr;
+        self.indirect = self.wrap_address(addr as usize) as u16;
     }
 
     fn skip_vx_ne_vy(&mut self, x: usize, y: usize) {
         if self.register_file[x] != self.register_file[y] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn draw_sprite(&mut self, x: usize, y: usize, n: usize) {
-        let slice = &self.memory[self.indirect as usize..self.indirect as usize + n];
-        let buf = slice.to_vec();
+        let Some(addresses) = self.indirect_addresses(n) else {
+            return;
+        };
+        let mut buf = [0; 16];
+        for (row, address) in buf.iter_mut().zip(addresses) {
+            *row = self.memory[address];
+        }
         let v_x = self.register_file[x] as usize;
         let v_y = self.register_file[y] as usize;
-        let job = DrawJob::Draw(Sprite { v_x, v_y, buf });
-        self.draw_queue.push_back(job);
+        let (width, height) = self.resolution();
+        let scale = self.pixel_scale();
+        let n_x = v_x & (width / scale - 1);
+        let n_y = v_y & (height / scale - 1);
+        let mut collision = false;
+
+        for (i, row) in buf[..n].iter().enumerate() {
+            for j in 0..8 {
+                if row & (0x80 >> j) == 0 || n_x + j >= width / scale || n_y + i >= height / scale {
+                    continue;
+                }
+                for (dx, dy) in (0..scale).flat_map(|dx| (0..scale).map(move |dy| (dx, dy))) {
+                    let bit = scale * (n_x + j) + dx + width * (scale * (n_y + i) + dy);
+                    let mask = 0x80 >> (bit % 8);
+                    collision |= self.video_memory[bit / 8] & mask != 0;
+                    self.video_memory[bit / 8] ^= mask;
+                }
+            }
+        }
+        self.register_file[0xF] = collision as u8;
+
+        let job = DrawJob::Draw(Sprite {
+            v_x: scale * n_x,
+            v_y: scale * n_y,
+            buf,
+            len: n,
+            scale,
+        });
+        self.queue_draw(job);
     }
 
     fn skip_pressed(&mut self, x: usize) {
-        if self.keyboard[self.register_file[x] as usize & 0xF].is_pressed() {
-            self.pc += 2;
+        if self.keyboard[self.register_file[x] as usize & 0xF] {
+            self.skip();
         }
     }
 
     fn skip_not_pressed(&mut self, x: usize) {
-        if !self.keyboard[self.register_file[x] as usize & 0xF].is_pressed() {
-            self.pc += 2;
+        if !self.keyboard[self.register_file[x] as usize & 0xF] {
+            self.skip();
         }
     }
 
     fn get_key(&mut self, _x: usize) {
         self.awaiting_key = true;
+        // --quirk-test answers the test suite's platform menu by pressing and releasing the
+        // platform's key, the same way a frontend's input would
+        if let Some(key) = self.menu_key.take() {
+            self.set_key(key as usize, true);
+            self.set_key(key as usize, false);
+        }
+    }
+
+    fn read_delay_timer(&mut self, x: usize) {
+        self.register_file[x] = self.delay_timer;
+    }
+
+    fn load_delay_timer(&mut self, x: usize) {
+        self.delay_timer = self.register_file[x];
     }
 
     fn load_sound_timer(&mut self, x: usize) {
@@ -337,27 +1008,66 @@ impl Chip8 {
     }
 
     fn load_hex_sprite(&mut self, x: usize) {
-        self.indirect = 5 * (self.register_file[self.instr.x] as u16 & 0x00FF);
+        // only the low nibble has a glyph, the rest of the byte would point past the font
+        let digit = self.register_file[x] as usize & 0xF;
+        self.indirect = self.font_address + (GLYPH_SIZE * digit) as u16;
     }
 
     fn store_bcd(&mut self, x: usize) {
-        let mut num = self.register_file[x];
-        for j in (0..3).rev() {
-            self.memory[self.indirect as usize + j] = num % 10;
-            num /= 10;
+        let Some(addresses) = self.indirect_addresses(3) else {
+            return;
+        };
+        self.check_reserved_write(3);
+        let num = self.register_file[x];
+        for (digit, address) in [num / 100, num / 10 % 10, num % 10]
+            .into_iter()
+            .zip(addresses)
+        {
+            self.memory[address] = digit;
         }
     }
 
     fn store_block(&mut self, x: usize) {
-        self.memory[self.indirect as usize..self.indirect as usize + x + 1]
-            .copy_from_slice(&self.register_file[..x + 1]);
-        self.indirect += x as u16 + 1;
+        let Some(addresses) = self.indirect_addresses(x + 1) else {
+            return;
+        };
+        self.check_reserved_write(x + 1);
+        for (reg, address) in addresses.enumerate() {
+            self.memory[address] = self.register_file[reg];
+        }
+        self.add_indirect(x + 1);
     }
 
     fn load_block(&mut self, x: usize) {
-        self.register_file[..x + 1]
-            .copy_from_slice(&self.memory[self.indirect as usize..self.indirect as usize + x + 1]);
-        self.indirect += x as u16 + 1;
+        let Some(addresses) = self.indirect_addresses(x + 1) else {
+            return;
+        };
+        for (reg, address) in addresses.enumerate() {
+            self.register_file[reg] = self.memory[address];
+        }
+        self.add_indirect(x + 1);
+    }
+
+    // XO-CHIP 5XY2 / 5XY3 walk from vx to vy, backwards when x > y, and leave I alone
+    fn store_range(&mut self, x: usize, y: usize) {
+        let Some(addresses) = self.indirect_addresses(x.abs_diff(y) + 1) else {
+            return;
+        };
+        self.check_reserved_write(x.abs_diff(y) + 1);
+        for (n, address) in addresses.enumerate() {
+            let reg = if x <= y { x + n } else { x - n };
+            self.memory[address] = self.register_file[reg];
+        }
+    }
+
+    fn load_range(&mut self, x: usize, y: usize) {
+        let Some(addresses) = self.indirect_addresses(x.abs_diff(y) + 1) else {
+            return;
+        };
+        for (n, address) in addresses.enumerate() {
+            let reg = if x <= y { x + n } else { x - n };
+            self.register_file[reg] = self.memory[address];
+        }
     }
 }
 
@@ -389,3 +1099,771 @@ impl InstructionDecode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Variant;
+
+    fn run(rom: &[u8], options: Options, cycles: usize) -> Chip8 {
+        let mut chip_8 = Chip8::from_rom(rom, options);
+        for _ in 0..cycles {
+            chip_8.instruction_cycle();
+        }
+        chip_8
+    }
+
+    #[test]
+    fn key_without_release_stays_held() {
+        let mut chip_8 = Chip8::from_rom(&[0x12, 0x00], Options::default());
+        chip_8.set_key(0xA, true);
+        for _ in 0..100 {
+            chip_8.instruction_cycle();
+        }
+        assert_eq!(chip_8.key_state(), 1 << 0xA);
+    }
+
+    #[test]
+    fn ex9e_skips_while_key_held() {
+        // V0 = 5, loop: SKP V0, JP loop, V1 = 1
+        let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0x61, 0x01];
+        let mut chip_8 = run(&rom, Options::default(), 7);
+        assert_eq!(chip_8.snapshot().v[1], 0);
+        chip_8.set_key(5, true);
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.snapshot().v[1], 0);
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.snapshot().v[1], 1);
+    }
+
+    #[test]
+    fn fault_at_end_of_memory_reports_its_address() {
+        let mut memory = vec![0; 0x10000];
+        memory[0xFFFE..].copy_from_slice(&[0x50, 0x01]);
+        let options = Options {
+            variant: Variant::XoChip,
+            strict: true,
+            ..Options::default()
+        };
+        let mut chip_8 = Chip8::with_memory(memory, options);
+        while !chip_8.halted() {
+            chip_8.instruction_cycle();
+        }
+        let fault = chip_8.fault().unwrap();
+        assert_eq!(
+            (fault.kind, fault.pc),
+            (FaultKind::UnknownInstruction, 0xFFFE)
+        );
+    }
+
+    fn schip(clear_on_res_switch: bool) -> Options {
+        Options {
+            variant: Variant::SuperChip,
+            quirks: Quirks {
+                clear_on_res_switch,
+                ..Quirks::default()
+            },
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn resolution_switch_keeps_picture() {
+        // draw the 0 glyph, 00FF, draw it again, 00FE
+        let rom = [0xD0, 0x05, 0x00, 0xFF, 0xD0, 0x05, 0x00, 0xFE];
+        let mut chip_8 = run(&rom, schip(false), 1);
+        let lores = chip_8.display().to_vec();
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.display(), lores);
+        chip_8.instruction_cycle();
+        let both = chip_8.display().to_vec();
+        assert_ne!(both, lores);
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.display(), both);
+    }
+
+    #[test]
+    fn resolution_switch_clears_with_quirk() {
+        let rom = [0xD0, 0x05, 0x00, 0xFF, 0xD0, 0x05, 0x00, 0xFE];
+        let mut chip_8 = run(&rom, schip(true), 1);
+        assert!(chip_8.display().iter().any(|&byte| byte != 0));
+        chip_8.instruction_cycle();
+        assert!(chip_8.display().iter().all(|&byte| byte == 0));
+        chip_8.instruction_cycle();
+        assert!(chip_8.display().iter().any(|&byte| byte != 0));
+        chip_8.instruction_cycle();
+        assert!(chip_8.display().iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn state_keeps_resolution() {
+        let mut chip_8 = run(&[0x00, 0xFF], schip(false), 1);
+        let mut state = chip_8.save_state();
+        chip_8.reset();
+        chip_8.load_state(&state).unwrap();
+        assert!(chip_8.hires);
+        // older layouts are refused rather than misread
+        state[0] = 4;
+        assert!(matches!(
+            chip_8.load_state(&state),
+            Err(StateError::Version(4))
+        ));
+    }
+
+    fn random_bytes(seed: u64) -> Vec<u8> {
+        // loop: CXNN into V0, JP loop
+        let mut chip_8 = Chip8::from_rom(
+            &[0xC0, 0xFF, 0x12, 0x00],
+            Options {
+                seed: Some(seed),
+                ..Options::default()
+            },
+        );
+        (0..32)
+            .map(|_| {
+                chip_8.instruction_cycle();
+                chip_8.instruction_cycle();
+                chip_8.snapshot().v[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seed_repeats_cxnn() {
+        assert_eq!(random_bytes(7), random_bytes(7));
+        assert_ne!(random_bytes(7), random_bytes(8));
+    }
+
+    #[test]
+    fn sound_timer_of_one_beeps_one_tick() {
+        // V0 = 1, ST = V0
+        let mut chip_8 = run(&[0x60, 0x01, 0xF0, 0x18], Options::default(), 2);
+        assert!(chip_8.sound_active());
+        chip_8.decrement_timers();
+        assert!(!chip_8.sound_active());
+    }
+
+    #[test]
+    fn vip_buzzer_stays_silent_at_one() {
+        let options = Options {
+            quirks: Quirks {
+                vip_buzzer: true,
+                ..Quirks::default()
+            },
+            ..Options::default()
+        };
+        let chip_8 = run(&[0x60, 0x01, 0xF0, 0x18], options.clone(), 2);
+        assert!(!chip_8.sound_active());
+        let mut chip_8 = run(&[0x60, 0x02, 0xF0, 0x18], options, 2);
+        assert!(chip_8.sound_active());
+        chip_8.decrement_timers();
+        assert!(!chip_8.sound_active());
+    }
+
+    // V0 = vx, V1 = vy, 8017
+    fn subn(vx: u8, vy: u8) -> (u8, u8) {
+        let chip_8 = run(&[0x60, vx, 0x61, vy, 0x80, 0x17], Options::default(), 3);
+        (chip_8.snapshot().v[0], chip_8.snapshot().v[0xF])
+    }
+
+    #[test]
+    fn subn_sets_vf_without_borrow() {
+        assert_eq!(subn(3, 5), (2, 1));
+        assert_eq!(subn(7, 7), (0, 1));
+        assert_eq!(subn(5, 3), (0xFE, 0));
+    }
+
+    #[test]
+    fn subn_into_vf_keeps_the_flag() {
+        // VF = 3, V1 = 5, 8F17
+        let chip_8 = run(&[0x6F, 0x03, 0x61, 0x05, 0x8F, 0x17], Options::default(), 3);
+        assert_eq!(chip_8.snapshot().v[0xF], 1);
+        let chip_8 = run(&[0x6F, 0x05, 0x61, 0x03, 0x8F, 0x17], Options::default(), 3);
+        assert_eq!(chip_8.snapshot().v[0xF], 0);
+    }
+
+    fn with_vf_reset(vf_reset: bool) -> Options {
+        Options {
+            quirks: Quirks {
+                vf_reset,
+                ..Quirks::default()
+            },
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn or_keeps_vf_by_default() {
+        // V0 = 0x0C, V1 = 0x03, VF = 7, 8011
+        let rom = [0x60, 0x0C, 0x61, 0x03, 0x6F, 0x07, 0x80, 0x11];
+        let chip_8 = run(&rom, with_vf_reset(false), 4);
+        assert_eq!(
+            (chip_8.snapshot().v[0], chip_8.snapshot().v[0xF]),
+            (0x0F, 7)
+        );
+    }
+
+    #[test]
+    fn or_resets_vf_with_quirk() {
+        let rom = [0x60, 0x0C, 0x61, 0x03, 0x6F, 0x07, 0x80, 0x11];
+        let chip_8 = run(&rom, with_vf_reset(true), 4);
+        assert_eq!(
+            (chip_8.snapshot().v[0], chip_8.snapshot().v[0xF]),
+            (0x0F, 0)
+        );
+        // 8F11, the reset wins over the result
+        let chip_8 = run(
+            &[0x6F, 0x0C, 0x61, 0x03, 0x8F, 0x11],
+            with_vf_reset(true),
+            3,
+        );
+        assert_eq!(chip_8.snapshot().v[0xF], 0);
+        let chip_8 = run(
+            &[0x6F, 0x0C, 0x61, 0x03, 0x8F, 0x11],
+            with_vf_reset(false),
+            3,
+        );
+        assert_eq!(chip_8.snapshot().v[0xF], 0x0F);
+    }
+
+    #[test]
+    fn delay_timer_reads_back() {
+        // V0 = 3, DT = V0, V1 = DT, V2 = DT
+        let rom = [0x60, 0x03, 0xF0, 0x15, 0xF1, 0x07, 0xF2, 0x07];
+        let mut chip_8 = run(&rom, Options::default(), 2);
+        chip_8.decrement_timers();
+        chip_8.instruction_cycle();
+        for _ in 0..5 {
+            chip_8.decrement_timers();
+        }
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.snapshot().v[1..3], [2, 0]);
+    }
+
+    #[test]
+    fn schip_lores_draws_2x2_blocks() {
+        // I = 0x208, V0 = 1, draw one row at (1, 1), sprite byte 0x80
+        let rom = [0xA2, 0x08, 0x60, 0x01, 0xD0, 0x01, 0x12, 0x06, 0x80];
+        let chip_8 = run(&rom, schip(false), 3);
+        let rows = chip_8.display_ascii();
+        let rows: Vec<_> = rows.lines().collect();
+        assert_eq!(rows.len(), HIRES_HEIGHT);
+        assert_eq!(&rows[2][..6], "..##..");
+        assert_eq!(&rows[3][..6], "..##..");
+        let lit = chip_8
+            .display()
+            .iter()
+            .map(|byte| byte.count_ones())
+            .sum::<u32>();
+        assert_eq!(lit, 4);
+        // the same rom on CHIP-8 lights one pixel
+        let chip_8 = run(&rom, Options::default(), 3);
+        assert_eq!(&chip_8.display_ascii().lines().nth(1).unwrap()[..3], ".#.");
+    }
+
+    #[test]
+    fn key_state_has_a_bit_per_key() {
+        let mut chip_8 = Chip8::from_rom(&[0x12, 0x00], Options::default());
+        chip_8.set_key(0, true);
+        chip_8.set_key(5, true);
+        assert_eq!(chip_8.key_state(), 1 << 0 | 1 << 5);
+        chip_8.set_key(0, false);
+        assert_eq!(chip_8.key_state(), 1 << 5);
+    }
+
+    // V3 = K, then spin
+    fn wait_for_key(events: &[(usize, bool)]) -> Chip8 {
+        let mut chip_8 = run(&[0xF3, 0x0A, 0x12, 0x02], Options::default(), 1);
+        for &(key, pressed) in events {
+            chip_8.set_key(key, pressed);
+        }
+        chip_8
+    }
+
+    #[test]
+    fn fx0a_latches_overlapping_presses() {
+        // A down, B down, A up: A was pressed and released first
+        let chip_8 = wait_for_key(&[(0xA, true), (0xB, true), (0xA, false)]);
+        assert!(!chip_8.waiting());
+        assert_eq!(chip_8.snapshot().v[3], 0xA);
+        // A down, B down, B up: B is released first though A went down first
+        let chip_8 = wait_for_key(&[(0xA, true), (0xB, true), (0xB, false)]);
+        assert_eq!(chip_8.snapshot().v[3], 0xB);
+        assert_eq!(chip_8.key_state(), 1 << 0xA);
+    }
+
+    #[test]
+    fn fx0a_ignores_keys_held_from_before() {
+        let mut chip_8 = Chip8::from_rom(&[0xF3, 0x0A, 0x12, 0x02], Options::default());
+        chip_8.set_key(1, true);
+        chip_8.instruction_cycle();
+        chip_8.set_key(1, false);
+        assert!(chip_8.waiting());
+        chip_8.set_key(2, true);
+        chip_8.set_key(2, false);
+        assert!(!chip_8.waiting());
+        assert_eq!(chip_8.snapshot().v[3], 2);
+    }
+
+    #[test]
+    fn random_memory_never_panics() {
+        const IMAGES: u64 = 32; // per variant and quirk setting
+        const CYCLES: u64 = 10_000;
+        let mut rng = Rng::new(0x5EED);
+        for variant in [Variant::Chip8, Variant::SuperChip, Variant::XoChip] {
+            for wrap_addresses in [false, true] {
+                let options = Options {
+                    variant,
+                    seed: Some(1),
+                    quirks: Quirks {
+                        wrap_addresses,
+                        ..Quirks::default()
+                    },
+                    ..Options::default()
+                };
+                for image in 0..IMAGES {
+                    let memory = (0..options.extensions().memory_size())
+                        .map(|_| rng.next_u8())
+                        .collect();
+                    let options = options.clone();
+                    let result = std::panic::catch_unwind(|| {
+                        let mut chip_8 = Chip8::with_memory(memory, options);
+                        for cycle in 0..CYCLES {
+                            if chip_8.halted() {
+                                break;
+                            }
+                            crate::headless::step(&mut chip_8, cycle);
+                        }
+                    });
+                    assert!(
+                        result.is_ok(),
+                        "{variant} image {image} panicked, wrap_addresses {wrap_addresses}"
+                    );
+                }
+            }
+        }
+    }
+
+    fn xochip() -> Options {
+        Options {
+            variant: Variant::XoChip,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn xochip_range_store_walks_both_ways() {
+        // V1 = 1, V2 = 2, V3 = 3, I = 0x300, 5132, I = 0x310, 5312
+        let rom = [
+            0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0xA3, 0x10, 0x53, 0x12,
+        ];
+        let chip_8 = run(&rom, xochip(), 7);
+        assert_eq!(chip_8.memory()[0x300..0x303], [1, 2, 3]);
+        assert_eq!(chip_8.memory()[0x310..0x313], [3, 2, 1]);
+        assert_eq!(chip_8.snapshot().i, 0x310);
+    }
+
+    #[test]
+    fn xochip_range_load_walks_both_ways() {
+        // I = 0x208, 5463, 5A83, then the data
+        let rom = [0xA2, 0x08, 0x54, 0x63, 0x5A, 0x83, 0x12, 0x06, 1, 2, 3];
+        let chip_8 = run(&rom, xochip(), 3);
+        let v = chip_8.snapshot().v;
+        assert_eq!(v[4..7], [1, 2, 3]);
+        assert_eq!(v[8..11], [3, 2, 1]);
+        assert_eq!(chip_8.snapshot().i, 0x208);
+    }
+
+    #[test]
+    fn skip_rejects_5xy1() {
+        let strict = Options {
+            strict: true,
+            ..Options::default()
+        };
+        let chip_8 = run(&[0x51, 0x21], strict, 1);
+        let fault = chip_8.fault().unwrap();
+        assert_eq!(
+            (fault.kind, fault.ir),
+            (FaultKind::UnknownInstruction, 0x5121)
+        );
+        // without --strict it neither skips like 5XY0 nor stops, V1 == V2 == 0
+        let chip_8 = run(&[0x51, 0x21, 0x63, 0x05], Options::default(), 2);
+        assert!(!chip_8.halted());
+        assert_eq!(chip_8.snapshot().v[3], 5);
+    }
+
+    #[test]
+    fn reset_powers_back_on() {
+        // V0 = 7, DT = ST = V0, CALL 0x20A, then at 0x20A: I = 0x300, FX55
+        let rom = [
+            0x60, 0x07, 0xF0, 0x15, 0xF0, 0x18, 0x22, 0x0A, 0x00, 0x00, 0xA3, 0x00, 0xF0, 0x55,
+        ];
+        let options = Options {
+            seed: Some(1),
+            ..Options::default()
+        };
+        let mut chip_8 = run(&rom, options.clone(), 6);
+        assert_eq!(chip_8.memory()[0x300], 7);
+        assert_eq!(chip_8.stack(), [0x208]);
+        chip_8.reset();
+        let registers = chip_8.snapshot();
+        assert_eq!(registers.pc, 0x200);
+        assert_eq!(registers.v, [0; 16]);
+        assert_eq!(registers.i, 0);
+        assert_eq!((registers.delay_timer, registers.sound_timer), (0, 0));
+        assert!(chip_8.stack().is_empty());
+        assert_eq!(chip_8.memory()[0x300], 0);
+        assert_eq!(
+            chip_8.save_state(),
+            Chip8::from_rom(&rom, options).save_state()
+        );
+    }
+
+    #[test]
+    fn xochip_reads_high_memory() {
+        let mut memory = vec![0; 0x10000];
+        memory[0x200..0x202].copy_from_slice(&[0xF2, 0x65]);
+        memory[0xF000..0xF003].copy_from_slice(&[1, 2, 3]);
+        let mut chip_8 = Chip8::with_memory(memory.clone(), xochip());
+        assert_eq!(chip_8.memory().len(), 0x10000);
+        chip_8.indirect = 0xF000;
+        chip_8.instruction_cycle();
+        assert!(chip_8.fault().is_none());
+        assert_eq!(chip_8.snapshot().v[..3], [1, 2, 3]);
+        assert_eq!(chip_8.snapshot().i, 0xF003);
+        // CHIP-8's 4 KB ends long before
+        let mut chip_8 = Chip8::with_memory(memory, Options::default());
+        chip_8.indirect = 0xF000;
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.fault().unwrap().kind, FaultKind::OutOfBounds);
+    }
+
+    fn wrapping(variant: Variant) -> Options {
+        Options {
+            variant,
+            quirks: Quirks {
+                wrap_addresses: true,
+                ..Quirks::default()
+            },
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn add_to_i_wraps_at_memory_size() {
+        // I = 0xFFF, V0 = 1, FX1E
+        let rom = [0xAF, 0xFF, 0x60, 0x01, 0xF0, 0x1E];
+        let chip_8 = run(&rom, wrapping(Variant::Chip8), 3);
+        assert_eq!(chip_8.snapshot().i, 0x000);
+        let chip_8 = run(&rom, wrapping(Variant::XoChip), 3);
+        assert_eq!(chip_8.snapshot().i, 0x1000);
+    }
+
+    #[test]
+    fn store_wraps_at_memory_size() {
+        // V0 = 0xAA, V1 = 0xBB, FX55 from the last byte of memory
+        let rom = [0x60, 0xAA, 0x61, 0xBB, 0xF1, 0x55];
+        for (variant, top) in [(Variant::Chip8, 0xFFF), (Variant::XoChip, 0xFFFF)] {
+            let mut chip_8 = run(&rom, wrapping(variant), 2);
+            chip_8.indirect = top;
+            chip_8.instruction_cycle();
+            assert!(chip_8.fault().is_none());
+            assert_eq!(chip_8.memory()[top as usize], 0xAA);
+            assert_eq!(chip_8.memory()[0], 0xBB);
+            assert_eq!(chip_8.snapshot().i, 1);
+        }
+        // without the quirk the store runs off the end
+        let mut chip_8 = run(&rom, Options::default(), 2);
+        chip_8.indirect = 0xFFF;
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.fault().unwrap().kind, FaultKind::OutOfBounds);
+    }
+
+    #[test]
+    fn sprite_origin_wraps_then_clips() {
+        // V0 = 70, V1 = 0, I = 0x20A, one row of 0xFF
+        let rom = [
+            0x60, 70, 0x61, 0x00, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xFF,
+        ];
+        let chip_8 = run(&rom, Options::default(), 4);
+        // 70 wraps to column 6, the row covers 6..14 without spilling past the right edge
+        assert_eq!(
+            chip_8.video_memory[..WIDTH / 8],
+            [0x03, 0xFC, 0, 0, 0, 0, 0, 0]
+        );
+        assert!(chip_8.video_memory[WIDTH / 8..]
+            .iter()
+            .all(|&byte| byte == 0));
+        // at column 60 the row is cut at the edge instead of wrapping round
+        let rom = [
+            0x60, 60, 0x61, 0x00, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xFF,
+        ];
+        let chip_8 = run(&rom, Options::default(), 4);
+        assert_eq!(
+            chip_8.video_memory[..WIDTH / 8],
+            [0, 0, 0, 0, 0, 0, 0, 0x0F]
+        );
+    }
+
+    #[test]
+    fn font_digit_uses_low_nibble() {
+        // V0 = 0x1A, F029
+        let chip_8 = run(&[0x60, 0x1A, 0xF0, 0x29], Options::default(), 2);
+        let i = chip_8.snapshot().i as usize;
+        assert_eq!(i, GLYPH_SIZE * 0xA);
+        assert_eq!(chip_8.memory()[i..i + GLYPH_SIZE], FONT[50..55]);
+    }
+
+    #[test]
+    fn font_digit_stays_in_the_font() {
+        for value in 0..=0xFF {
+            let chip_8 = run(&[0x60, value, 0xF0, 0x29], Options::default(), 2);
+            let i = chip_8.snapshot().i as usize;
+            assert!(i + GLYPH_SIZE <= FONT.len(), "{value:#04x} gave {i:#05x}");
+            assert_eq!(i, GLYPH_SIZE * (value as usize & 0xF));
+        }
+    }
+
+    #[test]
+    fn font_found_below_0x200() {
+        // a memory dump with the font at 0x050, V0 = 0x1A, F029
+        let mut memory = vec![0; ENTRY];
+        memory[0x50..0x50 + FONT.len()].copy_from_slice(&FONT);
+        memory.extend_from_slice(&[0x60, 0x1A, 0xF0, 0x29]);
+        let mut chip_8 = Chip8::with_memory(memory, Options::default());
+        chip_8.instruction_cycle();
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.snapshot().i as usize, 0x50 + GLYPH_SIZE * 0xA);
+    }
+
+    thread_local! {
+        static WARNINGS: std::cell::RefCell<Vec<String>> = Default::default();
+    }
+
+    struct CaptureWarnings;
+
+    impl log::Log for CaptureWarnings {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.with(|warnings| warnings.borrow_mut().push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    // warnings logged on this thread while `f` runs, other tests log on their own
+    fn warnings(f: impl FnOnce()) -> Vec<String> {
+        static CAPTURE: CaptureWarnings = CaptureWarnings;
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(log::LevelFilter::Warn);
+        f();
+        WARNINGS.with(|warnings| warnings.take())
+    }
+
+    #[test]
+    fn strict_warns_about_writes_to_the_font() {
+        let strict = Options {
+            strict: true,
+            ..Default::default()
+        };
+        // I = 0x050, V0 = 0x12, FX55 into the font
+        let store = [0xA0, 0x50, 0x60, 0x12, 0xF0, 0x55];
+        let logged = warnings(|| {
+            let chip_8 = run(&store, strict.clone(), 3);
+            assert_eq!(chip_8.memory[0x50], 0x12);
+        });
+        assert_eq!(logged.len(), 1);
+        assert!(logged[0].contains("0x050") && logged[0].contains("0x204"));
+
+        // reading it back with FX65 is fine, as is writing without --strict
+        let load = [0xA0, 0x50, 0xF0, 0x65];
+        assert!(warnings(|| drop(run(&load, strict, 2))).is_empty());
+        assert!(warnings(|| drop(run(&store, Options::default(), 3))).is_empty());
+    }
+
+    #[test]
+    fn full_draw_queue_collapses_to_a_repaint() {
+        // I = 0x000, draw the 0 glyph, loop
+        let mut chip_8 = run(&[0xA0, 0x00, 0xD0, 0x05, 0x12, 0x02], Options::default(), 1);
+        chip_8.draw_queue.clear();
+        for _ in 0..MAX_DRAW_QUEUE {
+            chip_8.instruction_cycle();
+            chip_8.instruction_cycle();
+        }
+        assert_eq!(chip_8.draw_queue.len(), MAX_DRAW_QUEUE);
+
+        chip_8.instruction_cycle();
+        chip_8.instruction_cycle();
+        assert_eq!(chip_8.draw_queue.len(), 2);
+        assert!(matches!(chip_8.draw_queue[0], DrawJob::Repaint));
+        assert!(matches!(chip_8.draw_queue[1], DrawJob::Draw(_)));
+    }
+
+    #[test]
+    fn exit_halts_with_its_extension() {
+        let rom = crate::asm::assemble("CLS\nEXIT\nCLS").unwrap();
+        let chip_8 = run(&rom, schip(false), 3);
+        assert!(chip_8.halted && chip_8.fault.is_none());
+        assert_eq!(chip_8.pc, 0x204);
+        assert!(matches!(
+            chip_8.system_events.back(),
+            Some(SystemEvent::Exit)
+        ));
+
+        // only the exit group stops it, on CHIP-8 or with just hires it's a SYS call
+        let exit_only = Options {
+            extensions: Some(Extensions {
+                schip_exit: true,
+                ..Extensions::default()
+            }),
+            ..Default::default()
+        };
+        assert!(run(&rom, exit_only, 3).halted);
+        let hires_only = Options {
+            extensions: Some(Extensions {
+                schip_hires: true,
+                ..Extensions::default()
+            }),
+            ..Default::default()
+        };
+        for options in [Options::default(), hires_only] {
+            let chip_8 = run(&rom, options, 3);
+            assert!(!chip_8.halted);
+            assert_eq!(chip_8.pc, 0x206);
+        }
+    }
+
+    #[test]
+    fn resolution_events_only_on_a_switch() {
+        // 00FF twice, 00FE twice
+        let mut chip_8 = run(
+            &[0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFE, 0x00, 0xFE],
+            schip(false),
+            4,
+        );
+        let events: Vec<_> = std::iter::from_fn(|| chip_8.poll_system_event())
+            .map(|event| match event {
+                SystemEvent::ResolutionChanged { hires } => hires,
+                event => panic!("unexpected {event:?}"),
+            })
+            .collect();
+        assert_eq!(events, [true, false]);
+        assert!(chip_8.draw_queue.is_empty());
+
+        // a reset from hires goes back to lores and repaints
+        let mut chip_8 = run(&[0x00, 0xFF], schip(false), 1);
+        chip_8.poll_system_event();
+        chip_8.reset();
+        assert!(matches!(
+            chip_8.poll_system_event(),
+            Some(SystemEvent::ResolutionChanged { hires: false })
+        ));
+        assert!(matches!(chip_8.poll_draw_queue(), Some(DrawJob::Repaint)));
+    }
+
+    #[test]
+    fn quirk_test_presses_the_platform_key() {
+        // the suite's menu in miniature: wait for a key and keep it at 0x300
+        let rom = crate::asm::assemble("LD V0, K\nLD I, 0x300\nLD [I], V0\nloop: JP loop").unwrap();
+        let mut options = Options::default();
+        assert!(options.set_quirk_test(3));
+        let mut chip_8 = run(&rom, options, 4);
+        assert_eq!(chip_8.memory[0x300], 3);
+
+        chip_8.reset();
+        assert_eq!(chip_8.memory[0x300], 0);
+        for _ in 0..4 {
+            chip_8.instruction_cycle();
+        }
+        assert_eq!(chip_8.memory[0x300], 3);
+    }
+
+    #[test]
+    fn state_carries_the_random_sequence() {
+        // CXFF over and over
+        let rom = [0xC0, 0xFF, 0x12, 0x00];
+        let seeded = |seed| Options {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let draws = |chip_8: &mut Chip8| -> Vec<u8> {
+            (0..16)
+                .map(|_| {
+                    chip_8.instruction_cycle();
+                    chip_8.instruction_cycle();
+                    chip_8.snapshot().v[0]
+                })
+                .collect()
+        };
+        let mut original = run(&rom, seeded(1), 6);
+        let state = original.save_state();
+        let mut restored = Chip8::from_rom(&rom, seeded(2));
+        restored.load_state(&state).unwrap();
+        assert_eq!(draws(&mut restored), draws(&mut original));
+    }
+
+    fn with_shift_vx(shift_vx: bool) -> Options {
+        Options {
+            quirks: Quirks {
+                shift_vx,
+                ..Quirks::default()
+            },
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn shift_in_place_flags_the_bit_shifted_out() {
+        for shift_vx in [false, true] {
+            // V5 = 0x81, then 8556 or 855E
+            for (shift, v5, vf) in [(0x56, 0x40, 1), (0x5E, 0x02, 1)] {
+                let chip_8 = run(&[0x65, 0x81, 0x85, shift], with_shift_vx(shift_vx), 2);
+                assert_eq!(chip_8.register_file[5], v5, "{shift:#04x} {shift_vx}");
+                assert_eq!(chip_8.register_file[0xF], vf, "{shift:#04x} {shift_vx}");
+            }
+            // V5 = 0x7E, neither end bit set
+            for (shift, v5) in [(0x56, 0x3F), (0x5E, 0xFC)] {
+                let rom = [0x65, 0x7E, 0x6F, 1, 0x85, shift];
+                let chip_8 = run(&rom, with_shift_vx(shift_vx), 3);
+                assert_eq!(chip_8.register_file[5], v5, "{shift:#04x} {shift_vx}");
+                assert_eq!(chip_8.register_file[0xF], 0, "{shift:#04x} {shift_vx}");
+            }
+            // VF = 0x82 then 8FF6, or 0x41 then 8FFE, the flag overwrites the result
+            for (vf, shift) in [(0x82, 0xF6), (0x41, 0xFE)] {
+                let chip_8 = run(&[0x6F, vf, 0x8F, shift], with_shift_vx(shift_vx), 2);
+                assert_eq!(chip_8.register_file[0xF], 0, "{shift:#04x} {shift_vx}");
+            }
+        }
+    }
+
+    #[test]
+    fn shift_vx_quirk_picks_the_shifted_register() {
+        // V5 = 0x81, V6 = 0x7E, then 8566 or 856E
+        for (shift_vx, shift, v5, vf) in [
+            (false, 0x66, 0x3F, 0),
+            (false, 0x6E, 0xFC, 0),
+            (true, 0x66, 0x40, 1),
+            (true, 0x6E, 0x02, 1),
+        ] {
+            let rom = [0x65, 0x81, 0x66, 0x7E, 0x85, shift];
+            let chip_8 = run(&rom, with_shift_vx(shift_vx), 3);
+            assert_eq!(chip_8.register_file[5], v5, "{shift:#04x} {shift_vx}");
+            assert_eq!(chip_8.register_file[6], 0x7E);
+            assert_eq!(chip_8.register_file[0xF], vf, "{shift:#04x} {shift_vx}");
+        }
+    }
+
+    #[test]
+    fn runaway_recursion_overflows_the_stack() {
+        // 2200 calls itself forever
+        let chip_8 = run(&[0x22, 0x00], Options::default(), 100);
+        let fault = chip_8.fault().unwrap();
+        assert_eq!((fault.kind, fault.pc), (FaultKind::StackOverflow, 0x200));
+        assert_eq!(chip_8.stack.len(), MAX_STACK_DEPTH);
+    }
+}
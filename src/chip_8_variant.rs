@@ -1,15 +1,78 @@
-use std::fmt::Debug;
+use std::{collections::HashSet, fmt::Debug};
 
-use winit::{event::ElementState, keyboard::KeyCode};
+use crate::{
+    draw_job::DrawJob, fault::Fault, options::Watchpoint, quirks::Quirks, save_state::StateError,
+    system_event::SystemEvent,
+};
 
-use crate::draw_job::DrawJob;
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+// a watched location the last instruction changed, `pc` and `ir` being that instruction
+#[derive(Debug, Clone, Copy)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    pub old: u16,
+    pub new: u16,
+    pub pc: u16,
+    pub ir: u16,
+}
 
 pub trait Chip8Variant: Debug {
     fn instruction_cycle(&mut self);
     fn decrement_timers(&mut self);
-    fn handle_input(&mut self, key_code: KeyCode, state: ElementState);
-    fn sound_timer(&self) -> u8;
+    fn timers_frozen(&self) -> bool;
+    fn set_timers_frozen(&mut self, frozen: bool);
+    fn set_key(&mut self, key: usize, pressed: bool);
+    fn key_state(&self) -> u16; // bit n set while key n is held
+    fn sound_active(&self) -> bool; // buzzer quirks applied, frontends make the sound
     fn waiting(&self) -> bool;
+    fn idling(&self) -> bool; // spinning on the delay timer until it next ticks
+    fn halted(&self) -> bool;
+    fn at_breakpoint(&self) -> bool; // stopped in front of a breakpoint's instruction
+    fn breakpoints(&self) -> &HashSet<u16>;
+    fn toggle_breakpoint(&mut self, address: u16) -> bool; // whether it's now set
+    fn poke_memory(&mut self, address: u16, value: u8) -> Option<u8>; // the old value
+    fn set_register(&mut self, x: usize, value: u8) -> Option<u8>; // the old value
+    fn watch_hit(&self) -> Option<WatchHit>;
+    fn fault(&self) -> Option<Fault>;
     fn poll_draw_queue(&mut self) -> Option<DrawJob>;
-    fn set_collision(&mut self, value: bool);
+    fn poll_system_event(&mut self) -> Option<SystemEvent>;
+    fn display(&self) -> &[u8];
+    fn display_dirty(&self) -> bool; // cleared by mark_display_clean once presented
+    fn mark_display_clean(&mut self);
+    fn memory(&self) -> &[u8];
+    fn stack(&self) -> &[u16]; // return addresses, oldest first
+    fn resolution(&self) -> (usize, usize);
+    fn opcode_counts(&self) -> [u64; 16];
+    fn instruction_count(&self) -> u64;
+    fn machine_cycles(&self) -> u64;
+    fn snapshot(&self) -> Registers;
+    fn quirks(&self) -> &Quirks;
+    fn set_quirk(&mut self, name: &str, value: bool) -> bool;
+    fn reset(&mut self);
+    fn save_state(&self) -> Vec<u8>;
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError>;
+
+    // `#` for lit pixels and `.` for dark ones, one line per row, for golden files
+    fn display_ascii(&self) -> String {
+        let (width, height) = self.resolution();
+        let display = self.display();
+        let mut ascii = String::with_capacity((width + 1) * height);
+        for y in 0..height {
+            for x in 0..width {
+                let bit = x + width * y;
+                let lit = display[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                ascii.push(if lit { '#' } else { '.' });
+            }
+            ascii.push('\n');
+        }
+        ascii
+    }
 }
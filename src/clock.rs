@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+// banks real elapsed time and pays it out as whole ticks of a fixed period
+#[derive(Debug)]
+pub struct Clock {
+    period: Duration,
+    owed: Duration,
+    max_ticks: u32,
+}
+
+impl Clock {
+    pub fn new(period: Duration, max_ticks: u32) -> Self {
+        Self {
+            period,
+            owed: Duration::ZERO,
+            max_ticks,
+        }
+    }
+
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.owed += elapsed;
+        let ticks = (self.owed.as_nanos() / self.period.as_nanos()) as u32;
+        if ticks > self.max_ticks {
+            // too far behind to be worth catching up on
+            self.owed = Duration::ZERO;
+            return self.max_ticks;
+        }
+        self.owed -= self.period * ticks;
+        ticks
+    }
+
+    pub fn reset(&mut self) {
+        self.owed = Duration::ZERO;
+    }
+}
+
+// instructions per frame for rates that don't divide evenly, the fraction owed
+// carries into the next frame so the average rate doesn't drift
+#[derive(Debug)]
+pub struct CycleBudget {
+    per_frame: f64,
+    owed: f64,
+}
+
+impl CycleBudget {
+    pub fn new(per_frame: f64) -> Self {
+        Self {
+            per_frame,
+            owed: 0.0,
+        }
+    }
+
+    pub fn next_frame(&mut self) -> u32 {
+        self.owed += self.per_frame;
+        let cycles = self.owed.floor();
+        self.owed -= cycles;
+        cycles as u32
+    }
+
+    // the last instruction of a frame can run past the budget, the next frame pays for it
+    pub fn overspend(&mut self, amount: u32) {
+        self.owed -= amount as f64;
+    }
+
+    pub fn set_per_frame(&mut self, per_frame: f64) {
+        self.per_frame = per_frame;
+    }
+}
+
+// instructions per frame that adapt to how long the last frames took to run them,
+// backing off quickly when they crowd out rendering and creeping back up once there's
+// room again, never leaving min..=max
+#[derive(Debug)]
+pub struct Governor {
+    min: f64,
+    max: f64,
+    per_frame: f64,
+    steady_frames: u32,
+}
+
+impl Governor {
+    const MAX_LOAD: f64 = 0.5; // share of the frame running instructions may take
+    const BACK_OFF: f64 = 0.8;
+    const CREEP: f64 = 1.02;
+    const SETTLE_FRAMES: u32 = 300; // unchanged this long counts as settled
+
+    pub fn new(min: f64, max: f64) -> Self {
+        Self {
+            min,
+            max,
+            per_frame: max,
+            steady_frames: 0,
+        }
+    }
+
+    // `work` is the time one frame's instructions took out of `frame`, returns the new
+    // instructions per frame when they change
+    pub fn adjust(&mut self, work: Duration, frame: Duration) -> Option<f64> {
+        let load = work.as_secs_f64() / frame.as_secs_f64();
+        let per_frame = if load > Self::MAX_LOAD {
+            (self.per_frame * Self::BACK_OFF).max(self.min)
+        } else if load < Self::MAX_LOAD / 2.0 {
+            (self.per_frame * Self::CREEP).min(self.max)
+        } else {
+            self.per_frame
+        };
+        if per_frame == self.per_frame {
+            self.steady_frames = self.steady_frames.saturating_add(1);
+            return None;
+        }
+        self.per_frame = per_frame;
+        self.steady_frames = 0;
+        Some(per_frame)
+    }
+
+    // true on the one frame the rate has held for long enough to call it settled
+    pub fn settled(&self) -> bool {
+        self.steady_frames == Self::SETTLE_FRAMES
+    }
+
+    pub fn per_frame(&self) -> f64 {
+        self.per_frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chip_8::Chip8, chip_8_variant::Chip8Variant};
+
+    const TICK: Duration = Duration::from_micros(16667);
+
+    #[test]
+    fn timers_follow_elapsed_time() {
+        // V0 = 200, DT = V0, ST = V0
+        let mut chip_8 = Chip8::from_rom(&[0x60, 200, 0xF0, 0x15, 0xF0, 0x18], Default::default());
+        for _ in 0..3 {
+            chip_8.instruction_cycle();
+        }
+        let mut clock = Clock::new(TICK, 8);
+        // uneven frames with hitches, 1.11 seconds in all
+        let frames = [5, 12, 40, 3, 90, 16, 17, 1, 66, 120]
+            .map(Duration::from_millis)
+            .repeat(3);
+        for &elapsed in &frames {
+            for _ in 0..clock.advance(elapsed) {
+                chip_8.decrement_timers();
+            }
+        }
+        let elapsed: Duration = frames.iter().sum();
+        let ticks = (elapsed.as_nanos() / TICK.as_nanos()) as u8;
+        assert_eq!(ticks, 66);
+        assert_eq!(chip_8.snapshot().delay_timer, 200 - ticks);
+        assert_eq!(chip_8.snapshot().sound_timer, 200 - ticks);
+    }
+
+    #[test]
+    fn clock_drops_time_past_catch_up() {
+        let mut clock = Clock::new(TICK, 4);
+        assert_eq!(clock.advance(Duration::from_secs(3)), 4);
+        assert_eq!(clock.advance(Duration::from_millis(16)), 0);
+        assert_eq!(clock.advance(Duration::from_millis(1)), 1);
+    }
+
+    #[test]
+    fn cycle_budget_keeps_the_rate() {
+        for per_frame in [10.5, 7.0 / 3.0, 500.0 / 60.0, 0.25] {
+            let mut budget = CycleBudget::new(per_frame);
+            let mut total = 0;
+            for frame in 1..=1000 {
+                total += budget.next_frame();
+                let target = per_frame * frame as f64;
+                assert!(
+                    (total as f64 - target).abs() < 1.0,
+                    "{per_frame} at {frame}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cycle_budget_pays_back_overspend() {
+        let mut budget = CycleBudget::new(10.0);
+        assert_eq!(budget.next_frame(), 10);
+        budget.overspend(3);
+        assert_eq!(budget.next_frame(), 7);
+        assert_eq!(budget.next_frame(), 10);
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use log::LevelFilter;
+
+use crate::{
+    batch,
+    options::Options,
+    palette::{Filter, Palette, PixelStyle, Theme},
+};
+
+const DEFAULT_REWIND_DEPTH: usize = 600; // 10 seconds of frames
+const DEFAULT_MAX_LOG_FILES: usize = 10;
+
+#[derive(Debug)]
+pub struct Config {
+    pub rom_paths: Vec<PathBuf>, // every rom given, the window switches between them
+    pub rewind_depth: usize,
+    pub benchmark: bool,
+    pub dump_display: Option<u64>,
+    pub batch: Option<PathBuf>,
+    pub batch_cycles: u64,
+    pub info: bool,
+    pub load_state: Option<PathBuf>,
+    pub assemble: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub screenshot_on_exit: Option<PathBuf>,
+    pub record_video: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub scenario: Option<PathBuf>,
+    pub onscreen_keypad: bool,
+    pub sound_bar: bool,
+    pub console: bool,
+    pub start_paused: bool,
+    pub palette: Palette,
+    pub crt_fade: bool,
+    pub anti_flicker: bool,
+    pub pixel_style: PixelStyle,
+    pub filter: Filter,
+    pub refresh_hz: Option<f64>,
+    pub vsync: bool,
+    pub frame_cap: Option<u32>,
+    pub ipf: Option<f64>,
+    pub accurate_timing: bool,
+    pub adaptive: bool,
+    pub idle_skip: bool,
+    pub deterministic_input: bool,
+    pub log_level: Option<LevelFilter>,
+    pub log_file: bool,
+    pub quiet: bool,
+    pub max_log_files: usize,
+    pub options: Options,
+}
+
+impl Config {
+    pub fn from_args() -> Self {
+        let mut args = std::env::args().skip(1);
+        let mut rom_paths = Vec::new();
+        let mut rewind_depth = DEFAULT_REWIND_DEPTH;
+        let mut benchmark = false;
+        let mut dump_display = None;
+        let mut batch = None;
+        let mut batch_cycles = batch::DEFAULT_CYCLES;
+        let mut info = false;
+        let mut load_state = None;
+        let mut assemble = None;
+        let mut output = None;
+        let mut record = None;
+        let mut screenshot_on_exit = None;
+        let mut record_video = None;
+        let mut replay = None;
+        let mut scenario: Option<PathBuf> = None;
+        let mut onscreen_keypad = false;
+        let mut sound_bar = false;
+        let mut console = false;
+        let mut start_paused = false;
+        let mut theme = None;
+        let mut palette = None;
+        let mut crt_fade = false;
+        let mut anti_flicker = false;
+        let mut pixel_style = None;
+        let mut filter = None;
+        let mut refresh_hz = None;
+        let mut vsync = true;
+        let mut frame_cap = None;
+        let mut ipf = None;
+        let mut accurate_timing = false;
+        let mut adaptive = false;
+        let mut idle_skip = false;
+        let mut deterministic_input = false;
+        let mut log_level = None;
+        let mut log_file = false;
+        let mut quiet = false;
+        let mut max_log_files = DEFAULT_MAX_LOG_FILES;
+        let mut options = Options::default();
+        let mut quirk_test = None;
+        let mut quirks = Vec::new();
+        let mut extensions = Vec::new();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--rewind-depth" => rewind_depth = parse_value(&mut args, &arg),
+                "--benchmark" => benchmark = true,
+                "--dump-display" => dump_display = Some(parse_value(&mut args, &arg)),
+                "--batch" => batch = Some(parse_value(&mut args, &arg)),
+                "--batch-cycles" => batch_cycles = parse_value(&mut args, &arg),
+                "--info" => info = true,
+                "--onscreen-keypad" => onscreen_keypad = true,
+                "--sound-bar" => sound_bar = true,
+                "--console" => console = true,
+                "--start-paused" => start_paused = true,
+                "--theme" => {
+                    let name: String = parse_value(&mut args, &arg);
+                    theme = Some(Theme::load(&name).unwrap_or_else(|err| panic!("{err}")));
+                }
+                "--palette" => palette = Some(parse_value(&mut args, &arg)),
+                "--crt-fade" => crt_fade = true,
+                "--anti-flicker" => anti_flicker = true,
+                "--pixel-style" => pixel_style = Some(parse_value(&mut args, &arg)),
+                "--filter" => filter = Some(parse_value(&mut args, &arg)),
+                "--no-vsync" => vsync = false,
+                "--ipf" => {
+                    let per_frame: f64 = parse_value(&mut args, &arg);
+                    assert!(per_frame > 0.0, "--ipf should be positive");
+                    ipf = Some(per_frame);
+                }
+                "--accurate-timing" => accurate_timing = true,
+                "--adaptive" => adaptive = true,
+                "--idle-skip" => idle_skip = true,
+                "--deterministic-input" => deterministic_input = true,
+                "--frame-cap" => {
+                    let fps: u32 = parse_value(&mut args, &arg);
+                    assert!(fps > 0, "--frame-cap should be positive");
+                    frame_cap = Some(fps);
+                }
+                "--refresh-hz" => {
+                    let hz: f64 = parse_value(&mut args, &arg);
+                    assert!(hz > 0.0, "--refresh-hz should be positive");
+                    refresh_hz = Some(hz);
+                }
+                "--load-state" => load_state = Some(parse_value(&mut args, &arg)),
+                "--assemble" => assemble = Some(parse_value(&mut args, &arg)),
+                "-o" => output = Some(parse_value(&mut args, &arg)),
+                "--record" => record = Some(parse_value(&mut args, &arg)),
+                "--screenshot-on-exit" => screenshot_on_exit = Some(parse_value(&mut args, &arg)),
+                "--record-video" => record_video = Some(parse_value(&mut args, &arg)),
+                "--replay" => replay = Some(parse_value(&mut args, &arg)),
+                "--scenario" => scenario = Some(parse_value(&mut args, &arg)),
+                "--log-level" => log_level = Some(parse_value(&mut args, &arg)),
+                "--log-file" => log_file = true,
+                "--quiet" => quiet = true,
+                "--max-log-files" => max_log_files = parse_value(&mut args, &arg),
+                "--strict" => options.strict = true,
+                "--raw-image" => options.raw_image = true,
+                "--vip-boot" => options.vip_boot = true,
+                "--fill" => options.fill = parse_value(&mut args, &arg),
+                "--seed" => options.seed = Some(parse_value(&mut args, &arg)),
+                "--variant" => options.variant = parse_value(&mut args, &arg),
+                "--quirk" => {
+                    let quirk: String = parse_value(&mut args, &arg);
+                    let (name, value) = quirk
+                        .split_once('=')
+                        .and_then(|(name, value)| Some((name.to_string(), value.parse().ok()?)))
+                        .expect("--quirk should be followed by name=true or name=false");
+                    quirks.push((name, value));
+                }
+                "--break" => {
+                    let address: String = parse_value(&mut args, &arg);
+                    let address = address.trim_start_matches("0x");
+                    let address = u16::from_str_radix(address, 16)
+                        .expect("--break should be followed by a hex address");
+                    options.breakpoints.insert(address);
+                }
+                "--watch" => {
+                    let watchpoint = parse_value(&mut args, &arg);
+                    options.watchpoints.insert(watchpoint);
+                }
+                "--extension" => {
+                    let extension: String = parse_value(&mut args, &arg);
+                    let (name, value) = extension
+                        .split_once('=')
+                        .and_then(|(name, value)| Some((name.to_string(), value.parse().ok()?)))
+                        .expect("--extension should be followed by name=true or name=false");
+                    extensions.push((name, value));
+                }
+                "--quirk-test" => quirk_test = Some(parse_value(&mut args, &arg)),
+                _ => rom_paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        // the theme only fills in what wasn't given as a flag
+        let theme = theme.unwrap_or_default();
+        let palette = palette.or(theme.palette).unwrap_or_default();
+        let pixel_style = pixel_style.or(theme.pixel_style).unwrap_or_default();
+        let filter = filter.or(theme.filter).unwrap_or_default();
+        let crt_fade = crt_fade || theme.crt_fade;
+        assert!(
+            filter == Filter::Nearest || pixel_style == PixelStyle::Square,
+            "--filter and --pixel-style can't be combined"
+        );
+
+        // the preset goes first so --quirk can still override it
+        if let Some(platform) = quirk_test {
+            if !options.set_quirk_test(platform) {
+                panic!("unknown quirk test platform {platform}");
+            }
+        }
+        for (name, value) in quirks {
+            if !options.quirks.set(&name, value) {
+                panic!("unknown quirk {name}");
+            }
+        }
+        // extensions start from whatever the variant brings
+        if !extensions.is_empty() {
+            let mut resolved = options.extensions();
+            for (name, value) in extensions {
+                if !resolved.set(&name, value) {
+                    panic!("unknown extension {name}");
+                }
+            }
+            options.extensions = Some(resolved);
+        }
+
+        // a scenario brings its own rom, state and input, and stands in for the rom path
+        if let Some(path) = &scenario {
+            assert!(
+                rom_paths.is_empty() && replay.is_none() && load_state.is_none(),
+                "--scenario can't be combined with a rom, --replay or --load-state"
+            );
+            rom_paths.push(path.clone());
+        }
+
+        // assembling and batches are the modes that don't run a single rom
+        assert!(
+            !rom_paths.is_empty() || assemble.is_some() || batch.is_some(),
+            "rom path should be specified"
+        );
+
+        Self {
+            rom_paths,
+            rewind_depth,
+            benchmark,
+            dump_display,
+            batch,
+            batch_cycles,
+            info,
+            load_state,
+            assemble,
+            output,
+            record,
+            screenshot_on_exit,
+            record_video,
+            replay,
+            scenario,
+            onscreen_keypad,
+            sound_bar,
+            console,
+            start_paused,
+            palette,
+            crt_fade,
+            anti_flicker,
+            pixel_style,
+            filter,
+            refresh_hz,
+            vsync,
+            frame_cap,
+            ipf,
+            accurate_timing,
+            adaptive,
+            idle_skip,
+            deterministic_input,
+            log_level,
+            log_file,
+            quiet,
+            max_log_files,
+            options,
+        }
+    }
+
+    // every mode starts on the first rom that loads, along with its index in rom_paths
+    pub fn first_rom(&self) -> (usize, Vec<u8>) {
+        self.rom_paths
+            .iter()
+            .enumerate()
+            .find_map(|(index, path)| Some((index, read_rom(path)?)))
+            .expect("a rom should be readable")
+    }
+}
+
+pub fn read_rom(path: &Path) -> Option<Vec<u8>> {
+    fs::read(path)
+        .map_err(|err| log::error!("Failed to load {}: {}", path.display(), err))
+        .ok()
+}
+
+fn parse_value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("{flag} should be followed by a valid value"))
+}
@@ -2,11 +2,20 @@
 pub enum DrawJob {
     Draw(Sprite),
     Clear,
+    Repaint, // the whole display from its buffer, covering the jobs before it
 }
 
 #[derive(Debug)]
 pub struct Sprite {
     pub v_x: usize,
     pub v_y: usize,
-    pub buf: Vec<u8>,
+    pub buf: [u8; 16],
+    pub len: usize,
+    pub scale: usize, // display pixels per sprite pixel, v_x / v_y are already scaled
+}
+
+impl Sprite {
+    pub fn rows(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
 }
@@ -0,0 +1,18 @@
+use crate::{chip_8::Chip8, chip_8_variant::Chip8Variant, config::Config, headless, screenshot};
+
+// runs `frames` timer frames without a window at the same pace as --batch and prints the
+// display as text, so a rom's screen can be checked against a golden file
+pub fn dump_display(config: &Config, frames: u64) {
+    let (_, rom) = config.first_rom();
+    let mut chip_8 = Chip8::from_rom(&rom, config.options.clone());
+    for cycle in 0..frames * headless::CYCLES_PER_FRAME {
+        if chip_8.halted() {
+            break;
+        }
+        headless::step(&mut chip_8, cycle);
+    }
+    print!("{}", chip_8.display_ascii());
+    if let Some(path) = &config.screenshot_on_exit {
+        screenshot::save(&chip_8, &config.palette, path);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rodio::Source;
+
+const FADE_DURATION: Duration = Duration::from_millis(5);
+
+// fades `source` in and out when the gate flips instead of cutting it, which clicks
+pub struct Envelope<S> {
+    source: S,
+    gate: Arc<AtomicBool>,
+    level: f32,
+    step: f32,
+}
+
+impl<S> Envelope<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, gate: Arc<AtomicBool>) -> Self {
+        let fade_samples =
+            source.sample_rate() as f32 * source.channels() as f32 * FADE_DURATION.as_secs_f32();
+        Self {
+            source,
+            gate,
+            level: 0.0,
+            step: 1.0 / fade_samples,
+        }
+    }
+}
+
+impl<S> Iterator for Envelope<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+        if self.gate.load(Ordering::Relaxed) {
+            self.level = (self.level + self.step).min(1.0);
+        } else {
+            self.level = (self.level - self.step).max(0.0);
+        }
+        Some(sample * self.level)
+    }
+}
+
+impl<S> Source for Envelope<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt::Display;
+
+use crate::options::Variant;
+
+// the instruction groups each variant adds, switchable one by one for roms that mix
+// them, a variant turns on its own groups and those of the variants before it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Extensions {
+    // SUPER-CHIP 00FE / 00FF and the 128x64 display they switch between
+    pub schip_hires: bool,
+    // SUPER-CHIP 00FD, stopping the machine for good
+    pub schip_exit: bool,
+    // XO-CHIP 5XY2 / 5XY3 register range store and load
+    pub xochip_ranges: bool,
+    // XO-CHIP's 64 KB of memory for I and pc to reach
+    pub xochip_memory: bool,
+}
+
+impl Extensions {
+    pub const NAMES: [&'static str; 4] = [
+        "schip_hires",
+        "schip_exit",
+        "xochip_ranges",
+        "xochip_memory",
+    ];
+
+    pub fn of(variant: Variant) -> Self {
+        Self {
+            schip_hires: variant >= Variant::SuperChip,
+            schip_exit: variant >= Variant::SuperChip,
+            xochip_ranges: variant >= Variant::XoChip,
+            xochip_memory: variant >= Variant::XoChip,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "schip_hires" => Some(self.schip_hires),
+            "schip_exit" => Some(self.schip_exit),
+            "xochip_ranges" => Some(self.xochip_ranges),
+            "xochip_memory" => Some(self.xochip_memory),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        match name {
+            "schip_hires" => self.schip_hires = value,
+            "schip_exit" => self.schip_exit = value,
+            "xochip_ranges" => self.xochip_ranges = value,
+            "xochip_memory" => self.xochip_memory = value,
+            _ => return false,
+        }
+        true
+    }
+
+    // the VIP's 4 KB unless the XO-CHIP memory is on
+    pub fn memory_size(&self) -> usize {
+        if self.xochip_memory {
+            0x10000
+        } else {
+            0x1000
+        }
+    }
+}
+
+// the names of the groups that are on, comma separated
+impl Display for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let active: Vec<_> = Self::NAMES
+            .into_iter()
+            .filter(|name| self.get(name).unwrap())
+            .collect();
+        if active.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", active.join(", "))
+        }
+    }
+}
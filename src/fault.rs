@@ -0,0 +1,26 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    UnknownInstruction = 1,
+    StackUnderflow = 2,
+    OutOfBounds = 3,
+    StackOverflow = 4,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub pc: u16,
+    pub ir: u16,
+}
+
+impl FaultKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::UnknownInstruction),
+            2 => Some(Self::StackUnderflow),
+            3 => Some(Self::OutOfBounds),
+            4 => Some(Self::StackOverflow),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::chip_8_variant::Chip8Variant;
+
+pub const CYCLES_PER_FRAME: u64 = 8; // close to the window's 500 Hz against 60 Hz timers
+
+// one instruction with nobody at the keys or the screen: a key is tapped so FX0A doesn't
+// stall the run, drawing is dropped and the timers tick after every CYCLES_PER_FRAME
+// cycles, counting `cycle` from 0
+pub fn step(chip_8: &mut dyn Chip8Variant, cycle: u64) {
+    if chip_8.waiting() {
+        chip_8.set_key(cycle as usize % 16, true);
+        chip_8.set_key(cycle as usize % 16, false);
+    }
+    chip_8.instruction_cycle();
+    while chip_8.poll_draw_queue().is_some() {}
+    if (cycle + 1) % CYCLES_PER_FRAME == 0 {
+        chip_8.decrement_timers();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_8::Chip8;
+
+    #[test]
+    fn taps_keys_and_ticks_timers() {
+        // V0 = 100, DT = V0, wait for a key into V1, then spin
+        let rom = [0x60, 100, 0xF0, 0x15, 0xF1, 0x0A, 0x12, 0x06];
+        let mut chip_8 = Chip8::from_rom(&rom, Default::default());
+        for cycle in 0..4 * CYCLES_PER_FRAME {
+            step(&mut chip_8, cycle);
+        }
+        assert!(!chip_8.waiting());
+        assert_eq!(chip_8.snapshot().delay_timer, 96);
+        assert!(chip_8.poll_draw_queue().is_none());
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use winit::event::ElementState;
+
+// one `seed <n>` line, then a `<frame> <hex key> down|up` line per key event, where
+// frame counts 60 Hz timer ticks since the rom started
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl InputRecorder {
+    pub fn create<P: AsRef<Path>>(path: P, seed: u64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writeln!(writer, "seed {seed}")?;
+        Ok(Self {
+            writer,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn record(&mut self, frame: u64, key: usize, state: ElementState) -> io::Result<()> {
+        let state = if state.is_pressed() { "down" } else { "up" };
+        writeln!(self.writer, "{frame} {key:X} {state}")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // everything recorded so far, read back from the file
+    pub fn recorded(&mut self) -> io::Result<String> {
+        self.flush()?;
+        fs::read_to_string(&self.path)
+    }
+}
+
+pub struct InputReplay {
+    pub seed: Option<u64>,
+    events: VecDeque<(u64, usize, ElementState)>,
+}
+
+impl InputReplay {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut seed = None;
+        let mut events = VecDeque::new();
+        for (n, line) in text.lines().enumerate() {
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("line {}", n + 1));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [] => (),
+                ["seed", value] => seed = Some(value.parse().map_err(|_| invalid())?),
+                [frame, key, state] => {
+                    let frame = frame.parse().map_err(|_| invalid())?;
+                    let key = usize::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|&key| key < 16)
+                        .ok_or_else(invalid)?;
+                    let state = match state {
+                        "down" => ElementState::Pressed,
+                        "up" => ElementState::Released,
+                        _ => return Err(invalid()),
+                    };
+                    events.push_back((frame, key, state));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Self { seed, events })
+    }
+
+    // the next event recorded at or before `frame`
+    pub fn due(&mut self, frame: u64) -> Option<(usize, ElementState)> {
+        let &(event_frame, key, state) = self.events.front()?;
+        if event_frame > frame {
+            return None;
+        }
+        self.events.pop_front();
+        Some((key, state))
+    }
+
+    pub fn finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
@@ -0,0 +1,22 @@
+#![allow(clippy::precedence)]
+
+// the emulator core, free of the windowing and audio crates so it also builds for
+// wasm32-unknown-unknown with `--no-default-features --features wasm`
+pub mod asm;
+pub mod chip_8;
+pub mod chip_8_variant;
+pub mod crc32;
+pub mod draw_job;
+pub mod extensions;
+pub mod fault;
+pub mod headless;
+pub mod options;
+pub mod png;
+pub mod quirks;
+pub mod rng;
+pub mod rom_info;
+pub mod save_state;
+pub mod system_event;
+pub mod timing;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,34 +1,134 @@
-#![feature(random)]
 #![allow(clippy::precedence)]
 
-use std::{fs::File, time::UNIX_EPOCH};
+use std::{
+    cmp::Reverse,
+    fs::{self, File},
+    io, process,
+    time::UNIX_EPOCH,
+};
 
 use app::App;
+use chip_8::Chip8;
+use chip_8_rs::{
+    asm, chip_8, chip_8_variant, draw_job, fault, headless, options, png, quirks, rng,
+    system_event, timing,
+};
+use config::Config;
 use env_logger::Target;
 use winit::event_loop::{ControlFlow, EventLoop};
 
 mod app;
-mod chip_8;
-mod chip_8_variant;
-mod draw_job;
+mod batch;
+mod benchmark;
+mod clock;
+mod config;
+mod console;
+mod dump;
+mod envelope;
+mod input_log;
+mod memory_view;
+mod palette;
+mod scenario;
+mod screenshot;
+mod video;
 
 fn main() {
-    init_logger();
+    let config = Config::from_args();
+    init_logger(&config);
+
+    if let Some(source) = &config.assemble {
+        let output = config
+            .output
+            .clone()
+            .unwrap_or_else(|| source.with_extension("ch8"));
+        let source_text = fs::read_to_string(source).expect("assembly source should be readable");
+        match asm::assemble(&source_text) {
+            Ok(rom) => {
+                fs::write(&output, &rom).expect("assembled rom should be writable");
+                println!("Assembled {} bytes to {}", rom.len(), output.display());
+            }
+            Err(err) => {
+                eprintln!("{}: {}", source.display(), err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    if config.info {
+        let (_, rom) = config.first_rom();
+        let chip_8 = Chip8::from_rom(&rom, config.options.clone());
+        println!("{}", chip_8.rom_info());
+        return;
+    }
+    if let Some(frames) = config.dump_display {
+        dump::dump_display(&config, frames);
+        return;
+    }
+    if config.benchmark {
+        benchmark::run(&config);
+        return;
+    }
+    if let Some(dir) = &config.batch {
+        batch::run(&config, dir, config.batch_cycles);
+        return;
+    }
 
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let rom_path = std::env::args()
-        .nth(1)
-        .expect("rom path should be specified");
-
-    let mut app = App::new(rom_path);
+    let mut app = App::new(config);
     event_loop.run_app(&mut app).unwrap();
 }
 
-fn init_logger() {
+// RUST_LOG is respected unless --log-level overrides it
+fn init_logger(config: &Config) {
+    // with no logger installed the max level stays off, so every log call is a single
+    // level check and nothing reaches stderr or logs/
+    if config.quiet {
+        return;
+    }
+    let mut builder = env_logger::builder();
+    if let Some(level) = config.log_level {
+        builder.filter_level(level);
+    }
+    if config.log_file {
+        match open_log_file(config.max_log_files) {
+            Ok(file) => {
+                builder.target(Target::Pipe(Box::new(file)));
+            }
+            Err(err) => eprintln!("couldn't open a log file, logging to stderr: {err}"),
+        }
+    }
+    builder.init();
+}
+
+fn open_log_file(max_log_files: usize) -> io::Result<File> {
+    // create_dir_all is a no-op when logs/ already exists
+    fs::create_dir_all("logs")?;
+    if let Err(err) = prune_log_files(max_log_files.saturating_sub(1)) {
+        eprintln!("couldn't prune old log files: {err}");
+    }
     let log_id = UNIX_EPOCH.elapsed().expect("time travel").as_secs();
-    let target_path = format!("logs/log-{}.txt", log_id);
-    let target = Target::Pipe(Box::new(File::create(target_path).unwrap()));
-    env_logger::builder().target(target).init();
+    File::create(format!("logs/log-{}.txt", log_id))
+}
+
+// keeps the newest `keep` logs, anything not named log-<timestamp>.txt is left alone
+fn prune_log_files(keep: usize) -> io::Result<()> {
+    let mut logs = Vec::new();
+    for entry in fs::read_dir("logs")? {
+        let path = entry?.path();
+        let log_id = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("log-")?.strip_suffix(".txt"))
+            .and_then(|log_id| log_id.parse::<u64>().ok());
+        if let Some(log_id) = log_id {
+            logs.push((log_id, path));
+        }
+    }
+    logs.sort_unstable_by_key(|&(log_id, _)| Reverse(log_id));
+    for (_, path) in logs.into_iter().skip(keep) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,208 @@
+use pixels::{Pixels, SurfaceTexture};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event_loop::ActiveEventLoop,
+    window::{Window, WindowId},
+};
+
+use crate::{app::draw_text, chip_8_variant::Chip8Variant};
+
+const ROWS: usize = 8; // rows shown around pc and around I each
+const ROW_BYTES: usize = 8;
+const CHAR: usize = 5; // glyph pitch
+const LINE: usize = 7; // glyph height plus a pixel either side
+const LINES: usize = 2 * ROWS + 1; // of the listing and the stack
+const LIST_WIDTH: usize = 3 + CHAR * 9 + 1; // breakpoint mark, then `AAAA OOOO`
+const MEMORY_WIDTH: usize = 1 + CHAR * (5 + 3 * ROW_BYTES);
+const STACK_LEFT: usize = LIST_WIDTH + MEMORY_WIDTH;
+const WIDTH: usize = STACK_LEFT + 2 + 4 * CHAR + 1;
+const HEIGHT: usize = LINE * LINES;
+const SCALE: u32 = 4;
+const BACKGROUND: [u8; 4] = [0x10, 0x10, 0x10, 0xff];
+const LIST_BACKGROUND: [u8; 4] = [0x18, 0x18, 0x30, 0xff];
+const BREAKPOINT: [u8; 4] = [0xe0, 0x30, 0x30, 0xff];
+const STACK_BACKGROUND: [u8; 4] = [0x30, 0x24, 0x10, 0xff];
+const PC_HIGHLIGHT: [u8; 4] = [0x80, 0x20, 0x20, 0xff];
+const I_HIGHLIGHT: [u8; 4] = [0x20, 0x60, 0x20, 0xff];
+
+// a second window with the instructions around pc down the left, the current one in
+// red and breakpoints marked, then memory around pc on top, its instruction in red,
+// memory around I below, its byte in green, and the call stack down the right, newest
+// first
+pub struct MemoryView {
+    window: Window,
+    pixels: Pixels,
+    cursor: PhysicalPosition<f64>,
+    list_start: isize, // address of the listing's first line, before memory when pc is low
+}
+
+impl MemoryView {
+    pub fn open(event_loop: &ActiveEventLoop) -> Self {
+        let window_attributes = Window::default_attributes()
+            .with_title("CHIP-8 memory")
+            .with_inner_size(PhysicalSize::new(
+                SCALE * WIDTH as u32,
+                SCALE * HEIGHT as u32,
+            ));
+        let window = event_loop.create_window(window_attributes).unwrap();
+        let pixels = create_pixels(&window).unwrap();
+        Self {
+            window,
+            pixels,
+            cursor: PhysicalPosition::default(),
+            list_start: 0,
+        }
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            log::debug!(
+                "Skipping the memory view resize to {}x{}",
+                size.width,
+                size.height
+            );
+        } else if let Err(error) = self.pixels.resize_surface(size.width, size.height) {
+            log::warn!("Couldn't resize the memory view surface: {error}");
+        }
+    }
+
+    pub fn set_cursor(&mut self, cursor: PhysicalPosition<f64>) {
+        self.cursor = cursor;
+    }
+
+    // the address of the listing line under the cursor, if it's on one
+    pub fn listed_address(&self, memory_size: usize) -> Option<u16> {
+        let position = (self.cursor.x as f32, self.cursor.y as f32);
+        let (x, y) = self.pixels.window_pos_to_pixel(position).ok()?;
+        if x >= LIST_WIDTH {
+            return None;
+        }
+        let address = self.list_start + 2 * (y / LINE) as isize;
+        usize::try_from(address)
+            .ok()
+            .filter(|&address| address + 1 < memory_size)
+            .map(|address| address as u16)
+    }
+
+    pub fn draw(&mut self, chip_8: &dyn Chip8Variant) {
+        let registers = chip_8.snapshot();
+        let memory = chip_8.memory();
+        let frame = self.pixels.frame_mut();
+
+        for pixel in frame.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&BACKGROUND);
+        }
+
+        // the listing follows pc, keeping it on the middle line
+        fill(frame, 0, 0, LIST_WIDTH, HEIGHT, LIST_BACKGROUND);
+        self.list_start = registers.pc as isize - 2 * (LINES / 2) as isize;
+        for line in 0..LINES {
+            let address = self.list_start + 2 * line as isize;
+            let Some(address) = usize::try_from(address)
+                .ok()
+                .filter(|&address| address + 1 < memory.len())
+            else {
+                continue;
+            };
+            let y = LINE * line;
+            if address == registers.pc as usize {
+                fill(frame, 2, y, LIST_WIDTH - 2, LINE, PC_HIGHLIGHT);
+            }
+            if chip_8.breakpoints().contains(&(address as u16)) {
+                fill(frame, 0, y + 2, 2, 3, BREAKPOINT);
+            }
+            let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+            draw_text(
+                frame,
+                WIDTH,
+                3,
+                y + 1,
+                &format!("{address:04X} {opcode:04X}"),
+            );
+        }
+
+        draw_memory(frame, memory, 0, registers.pc as usize, 2, PC_HIGHLIGHT);
+        let i_top = LINE * (ROWS + 1);
+        draw_memory(frame, memory, i_top, registers.i as usize, 1, I_HIGHLIGHT);
+
+        fill(
+            frame,
+            STACK_LEFT,
+            0,
+            WIDTH - STACK_LEFT,
+            HEIGHT,
+            STACK_BACKGROUND,
+        );
+        for (n, address) in chip_8.stack().iter().rev().take(LINES).enumerate() {
+            draw_text(
+                frame,
+                WIDTH,
+                STACK_LEFT + 2,
+                1 + LINE * n,
+                &format!("{address:04X}"),
+            );
+        }
+        // a lost surface is rebuilt for the next frame, like the main window's
+        if let Err(error) = self.pixels.render() {
+            log::warn!("Rendering the memory view failed: {error}, recreating the surface");
+            match create_pixels(&self.window) {
+                Ok(pixels) => self.pixels = pixels,
+                Err(error) => log::error!("Couldn't recreate the surface: {error}"),
+            }
+        }
+    }
+}
+
+fn create_pixels(window: &Window) -> Result<Pixels, pixels::Error> {
+    let size = window.inner_size();
+    let surface_texture = SurfaceTexture::new(size.width, size.height, window);
+    Pixels::new(WIDTH as u32, HEIGHT as u32, surface_texture)
+}
+
+// ROWS rows of ROW_BYTES bytes, each after its address, with `address` on the fourth
+// row where memory allows and the `len` bytes from it highlighted
+fn draw_memory(
+    frame: &mut [u8],
+    memory: &[u8],
+    top: usize,
+    address: usize,
+    len: usize,
+    highlight: [u8; 4],
+) {
+    let first_row = (address / ROW_BYTES)
+        .saturating_sub(ROWS / 2 - 1)
+        .min(memory.len() / ROW_BYTES - ROWS);
+    for row in 0..ROWS {
+        let row_address = (first_row + row) * ROW_BYTES;
+        let y = top + LINE * row;
+        draw_text(
+            frame,
+            WIDTH,
+            LIST_WIDTH + 1,
+            y + 1,
+            &format!("{row_address:04X}"),
+        );
+        for (n, byte) in memory[row_address..row_address + ROW_BYTES]
+            .iter()
+            .enumerate()
+        {
+            let x = LIST_WIDTH + 1 + CHAR * (5 + 3 * n);
+            if (address..address + len).contains(&(row_address + n)) {
+                fill(frame, x - 1, y, 2 * CHAR + 1, LINE, highlight);
+            }
+            draw_text(frame, WIDTH, x, y + 1, &format!("{byte:02X}"));
+        }
+    }
+}
+
+fn fill(frame: &mut [u8], x: usize, y: usize, width: usize, height: usize, color: [u8; 4]) {
+    for row in frame[4 * WIDTH * y..4 * WIDTH * (y + height)].chunks_exact_mut(4 * WIDTH) {
+        for pixel in row[4 * x..4 * (x + width)].chunks_exact_mut(4) {
+            pixel.copy_from_slice(&color);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::HashSet, fmt::Display, str::FromStr};
+
+use crate::{extensions::Extensions, quirks::Quirks};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Variant {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub strict: bool, // extra runtime checks that point at rom bugs
+    pub variant: Variant,
+    pub extensions: Option<Extensions>, // the variant's own when unset
+    pub quirks: Quirks,
+    pub seed: Option<u64>,      // CXNN seed, taken from the clock when unset
+    pub quirk_test: Option<u8>, // platform key to press on the test suite's quirks menu
+    pub raw_image: bool,        // load the rom over all of memory instead of at 0x200
+    pub vip_boot: bool,         // power on with uninitialised memory and registers
+    pub fill: Fill,             // what memory and registers hold before the rom loads
+    pub breakpoints: HashSet<u16>,
+    pub watchpoints: HashSet<Watchpoint>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fill {
+    #[default]
+    Zero,
+    Ff,
+    Random,
+    Pattern, // each byte holds the low byte of its address, registers their index
+}
+
+// a location checked for changes around every instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Watchpoint {
+    V(usize),
+    I,
+    Memory(u16),
+}
+
+impl Options {
+    pub fn extensions(&self) -> Extensions {
+        self.extensions
+            .unwrap_or_else(|| Extensions::of(self.variant))
+    }
+
+    // Timendus' chip8-test-suite quirks rom asks for 1 (CHIP-8), 2 (SUPER-CHIP) or 3
+    // (XO-CHIP) on its menu, so match the variant and quirks to what that platform expects
+    pub fn set_quirk_test(&mut self, platform: u8) -> bool {
+        let (variant, clear_on_res_switch, vf_reset, shift_vx) = match platform {
+            1 => (Variant::Chip8, false, true, false),
+            2 => (Variant::SuperChip, false, false, true),
+            3 => (Variant::XoChip, true, false, false),
+            _ => return false,
+        };
+        self.variant = variant;
+        self.quirks = Quirks {
+            clear_on_res_switch,
+            vf_reset,
+            shift_vx,
+            ..Quirks::default()
+        };
+        self.quirk_test = Some(platform);
+        true
+    }
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Chip8 => write!(f, "CHIP-8"),
+            Self::SuperChip => write!(f, "SUPER-CHIP"),
+            Self::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chip8" => Ok(Self::Chip8),
+            "schip" => Ok(Self::SuperChip),
+            "xochip" => Ok(Self::XoChip),
+            _ => Err(format!("unsupported variant {s}")),
+        }
+    }
+}
+
+impl FromStr for Fill {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(Self::Zero),
+            "ff" => Ok(Self::Ff),
+            "random" => Ok(Self::Random),
+            "pattern" => Ok(Self::Pattern),
+            _ => Err(format!("unsupported fill {s}")),
+        }
+    }
+}
+
+impl Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V(x) => write!(f, "V{x:X}"),
+            Self::I => write!(f, "I"),
+            Self::Memory(address) => write!(f, "{address:03X}"),
+        }
+    }
+}
+
+// `V5`, `I`, or a hex memory address with or without `0x`
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_uppercase();
+        if upper == "I" {
+            return Ok(Self::I);
+        }
+        if let Some(x) = upper.strip_prefix('V').filter(|digit| digit.len() == 1) {
+            return usize::from_str_radix(x, 16)
+                .map(Self::V)
+                .map_err(|_| format!("unknown register {s}"));
+        }
+        u16::from_str_radix(upper.trim_start_matches("0X"), 16)
+            .map(Self::Memory)
+            .map_err(|_| format!("unknown watch location {s}"))
+    }
+}
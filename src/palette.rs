@@ -0,0 +1,243 @@
+use std::{fs, path::Path, str::FromStr};
+
+// rgb for each 2-bit pixel value, plane 0 is the low bit and plane 1 the high bit
+#[derive(Debug, Clone, Copy)]
+pub struct Palette([[u8; 3]; 4]);
+
+impl Palette {
+    // always fully opaque, some backends show stale alpha through the frame
+    pub fn color(&self, value: usize) -> [u8; 4] {
+        let [r, g, b] = self.0[value & 0b11];
+        [r, g, b, 0xff]
+    }
+
+    // the off and on colours of a one plane picture, as the png encoder takes them
+    pub fn off_on(&self) -> [[u8; 3]; 2] {
+        [self.0[0], self.0[1]]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self([
+            [0x00, 0x00, 0x00],
+            [0xff, 0xff, 0xff],
+            [0x80, 0x80, 0x80],
+            [0x00, 0xcc, 0xcc],
+        ])
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    // four comma separated RRGGBB hex colors, a leading # is allowed
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let colors = s
+            .split(',')
+            .map(|color| {
+                let hex = color.trim().trim_start_matches('#');
+                match u32::from_str_radix(hex, 16) {
+                    Ok(rgb) if hex.len() == 6 => {
+                        let [_, r, g, b] = rgb.to_be_bytes();
+                        Ok([r, g, b])
+                    }
+                    _ => Err(format!("invalid color {color}")),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let colors = colors
+            .try_into()
+            .map_err(|_| format!("palette needs 4 colors, got {s}"))?;
+        Ok(Self(colors))
+    }
+}
+
+// how each display pixel is drawn, anything but square renders every pixel as a
+// CELL x CELL block so there's room for the gaps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PixelStyle {
+    #[default]
+    Square,
+    Dot,
+    Scanline,
+}
+
+impl PixelStyle {
+    const CELL: usize = 4;
+
+    pub fn cell(&self) -> usize {
+        match self {
+            Self::Square => 1,
+            Self::Dot | Self::Scanline => Self::CELL,
+        }
+    }
+
+    // how much of the pixel's color shows at (x, y) inside its cell, 0.0 is background
+    pub fn shade(&self, x: usize, y: usize) -> f32 {
+        match self {
+            Self::Square => 1.0,
+            Self::Scanline if y == Self::CELL - 1 => 0.35,
+            Self::Scanline => 1.0,
+            Self::Dot => {
+                let center = Self::CELL as f32 / 2.0;
+                let (dx, dy) = (x as f32 + 0.5 - center, y as f32 + 0.5 - center);
+                if dx * dx + dy * dy <= center * center {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for PixelStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Self::Square),
+            "dot" => Ok(Self::Dot),
+            "scanline" => Ok(Self::Scanline),
+            _ => Err(format!("unknown pixel style {s}")),
+        }
+    }
+}
+
+// how the canvas is scaled up before the gpu stretches it with nearest sampling,
+// `linear` blends neighbouring pixels and `scale2x` rounds off diagonal edges
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Filter {
+    #[default]
+    Nearest,
+    Linear,
+    Scale2x,
+}
+
+impl Filter {
+    pub fn cell(&self) -> usize {
+        match self {
+            Self::Nearest => 1,
+            Self::Linear => 4,
+            Self::Scale2x => 2,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "linear" => Ok(Self::Linear),
+            "scale2x" => Ok(Self::Scale2x),
+            _ => Err(format!("unknown filter {s}")),
+        }
+    }
+}
+
+// a preset for the display options, any it leaves out keep their defaults and flags
+// given alongside it still win
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Theme {
+    pub palette: Option<Palette>,
+    pub pixel_style: Option<PixelStyle>,
+    pub filter: Option<Filter>,
+    pub crt_fade: bool,
+}
+
+impl Theme {
+    const BUILT_IN: [(&'static str, &'static str); 3] = [
+        ("classic", "palette = 000000,ffffff,808080,00cccc"),
+        (
+            "green-phosphor",
+            "palette = 001a08,33ff66,1a8033,99ffbb\npixel_style = scanline\ncrt_fade = true",
+        ),
+        (
+            "amber",
+            "palette = 1a0f00,ffb000,805800,ffd580\npixel_style = scanline\ncrt_fade = true",
+        ),
+    ];
+
+    // a built-in theme by name, then a theme file at that path or themes/<name>.theme
+    pub fn load(name: &str) -> Result<Self, String> {
+        if let Some((_, text)) = Self::BUILT_IN
+            .iter()
+            .find(|(built_in, _)| *built_in == name)
+        {
+            return text.parse();
+        }
+        let path = Path::new(name);
+        let path = if path.exists() {
+            path.to_path_buf()
+        } else {
+            Path::new("themes").join(name).with_extension("theme")
+        };
+        fs::read_to_string(&path)
+            .map_err(|err| format!("can't read theme {}: {}", path.display(), err))?
+            .parse()
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    // `key = value` lines named like the flags, `#` starts a comment
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut theme = Self::default();
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| format!("expected key = value, got {line}"))?;
+            match key {
+                "palette" => theme.palette = Some(value.parse()?),
+                "pixel_style" => theme.pixel_style = Some(value.parse()?),
+                "filter" => theme.filter = Some(value.parse()?),
+                "crt_fade" => {
+                    theme.crt_fade = value
+                        .parse()
+                        .map_err(|_| format!("crt_fade should be true or false, got {value}"))?
+                }
+                _ => return Err(format!("unknown theme key {key}")),
+            }
+        }
+        Ok(theme)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn planes_pick_colors() {
+        let palette: Palette = "#000000,ff0000,00ff00,0000ff".parse().unwrap();
+        let value = |plane_0: usize, plane_1: usize| plane_0 | plane_1 << 1;
+        assert_eq!(palette.color(value(0, 0)), [0x00, 0x00, 0x00, 0xff]);
+        assert_eq!(palette.color(value(1, 0)), [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(palette.color(value(0, 1)), [0x00, 0xff, 0x00, 0xff]);
+        assert_eq!(palette.color(value(1, 1)), [0x00, 0x00, 0xff, 0xff]);
+        // only the two plane bits count
+        assert_eq!(palette.color(0b101), palette.color(0b01));
+    }
+
+    #[test]
+    fn palette_needs_four_colors() {
+        assert!("000000,ffffff".parse::<Palette>().is_err());
+        assert!("000000,ffffff,12345,000000".parse::<Palette>().is_err());
+        assert_eq!(
+            Palette::default().color(3),
+            "000000,ffffff,808080,00cccc"
+                .parse::<Palette>()
+                .unwrap()
+                .color(3)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use crate::crc32::crc32;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// 1 bit indexed colour png from rows packed 8 pixels to a byte, most significant bit
+// first like the display buffer, the image data goes in stored deflate blocks so there's
+// no compressor to carry
+pub fn encode(width: usize, height: usize, rows: &[u8], colors: [[u8; 3]; 2]) -> Vec<u8> {
+    let mut png = start(width, height, colors);
+    write_chunk(
+        &mut png,
+        b"IDAT",
+        &zlib_stored(&image_data(width, height, rows)),
+    );
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// animated png of same sized frames laid out like `encode`'s, each shown for its delay
+// as a numerator and denominator in seconds and looping forever, viewers without apng
+// support show the first
+pub fn encode_animation(
+    width: usize,
+    height: usize,
+    frames: &[(Vec<u8>, (u16, u16))],
+    colors: [[u8; 3]; 2],
+) -> Vec<u8> {
+    let mut png = start(width, height, colors);
+    let mut control = Vec::with_capacity(8);
+    control.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    control.extend_from_slice(&0u32.to_be_bytes()); // plays
+    write_chunk(&mut png, b"acTL", &control);
+
+    // frame controls and frame data share one sequence
+    let mut sequence = 0u32;
+    for (n, (rows, (delay_num, delay_den))) in frames.iter().enumerate() {
+        let mut frame = Vec::with_capacity(26);
+        frame.extend_from_slice(&sequence.to_be_bytes());
+        frame.extend_from_slice(&(width as u32).to_be_bytes());
+        frame.extend_from_slice(&(height as u32).to_be_bytes());
+        frame.extend_from_slice(&[0; 8]); // x and y offsets
+        frame.extend_from_slice(&delay_num.to_be_bytes());
+        frame.extend_from_slice(&delay_den.to_be_bytes());
+        frame.extend_from_slice(&[0, 0]); // no disposal, replace the canvas
+        write_chunk(&mut png, b"fcTL", &frame);
+        sequence += 1;
+
+        let image = zlib_stored(&image_data(width, height, rows));
+        if n == 0 {
+            write_chunk(&mut png, b"IDAT", &image);
+        } else {
+            let mut data = Vec::with_capacity(4 + image.len());
+            data.extend_from_slice(&sequence.to_be_bytes());
+            data.extend_from_slice(&image);
+            write_chunk(&mut png, b"fdAT", &data);
+            sequence += 1;
+        }
+    }
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn start(width: usize, height: usize, colors: [[u8; 3]; 2]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[1, 3, 0, 0, 0]); // bit depth, indexed, no interlace
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", &colors.concat());
+    png
+}
+
+fn image_data(width: usize, height: usize, rows: &[u8]) -> Vec<u8> {
+    let stride = width.div_ceil(8);
+    let mut image = Vec::with_capacity(height * (stride + 1));
+    for row in rows.chunks_exact(stride).take(height) {
+        image.push(0); // no filter
+        image.extend_from_slice(row);
+    }
+    image
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let mut zlib = Vec::with_capacity(data.len() + 5 * blocks + 6);
+    zlib.extend_from_slice(&[0x78, 0x01]);
+    for n in 0..blocks {
+        let block = &data[n * MAX_STORED_BLOCK..data.len().min((n + 1) * MAX_STORED_BLOCK)];
+        let len = block.len() as u16;
+        zlib.push((n + 1 == blocks) as u8); // final block flag, stored type
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_keep_their_delay_fraction() {
+        let frames = [(vec![0xF0], (1, 60)), (vec![0x0F], (3, 60))];
+        let png = encode_animation(8, 1, &frames, [[0; 3], [0xFF; 3]]);
+        let delays: Vec<_> = png
+            .windows(4)
+            .enumerate()
+            .filter(|(_, tag)| tag == b"fcTL")
+            .map(|(at, _)| &png[at + 4 + 20..at + 4 + 24])
+            .collect();
+        assert_eq!(delays, [[0, 1, 0, 60], [0, 3, 0, 60]]);
+    }
+}
@@ -0,0 +1,54 @@
+#[derive(Debug, Clone, Default)]
+pub struct Quirks {
+    // SUPER-CHIP 1.1 keeps the picture when 00FE / 00FF switch resolution (Car Race,
+    // Spacefight 2091! redraw over it), Octo and XO-CHIP wipe it (most Octo jam games)
+    pub clear_on_res_switch: bool,
+    // the VIP buzzer stays silent when the sound timer is set to 1
+    pub vip_buzzer: bool,
+    // keeps a key held until the next instruction so a tap between two cycles still
+    // reaches EX9E / EXA1 polling loops
+    pub hold_taps: bool,
+    // addresses past the end of memory wrap to the start instead of faulting
+    pub wrap_addresses: bool,
+    // the VIP's 8XY1 / 8XY2 / 8XY3 leave VF zeroed as a side effect of how its
+    // interpreter runs them, SUPER-CHIP and XO-CHIP leave it alone
+    pub vf_reset: bool,
+    // SUPER-CHIP's 8XY6 / 8XYE shift Vx and ignore Vy, the VIP and XO-CHIP shift Vy into Vx
+    pub shift_vx: bool,
+}
+
+impl Quirks {
+    pub const NAMES: [&'static str; 6] = [
+        "clear_on_res_switch",
+        "vip_buzzer",
+        "hold_taps",
+        "wrap_addresses",
+        "vf_reset",
+        "shift_vx",
+    ];
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "clear_on_res_switch" => Some(self.clear_on_res_switch),
+            "vip_buzzer" => Some(self.vip_buzzer),
+            "hold_taps" => Some(self.hold_taps),
+            "wrap_addresses" => Some(self.wrap_addresses),
+            "vf_reset" => Some(self.vf_reset),
+            "shift_vx" => Some(self.shift_vx),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        match name {
+            "clear_on_res_switch" => self.clear_on_res_switch = value,
+            "vip_buzzer" => self.vip_buzzer = value,
+            "hold_taps" => self.hold_taps = value,
+            "wrap_addresses" => self.wrap_addresses = value,
+            "vf_reset" => self.vf_reset = value,
+            "shift_vx" => self.shift_vx = value,
+            _ => return false,
+        }
+        true
+    }
+}
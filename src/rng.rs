@@ -0,0 +1,39 @@
+use std::time::UNIX_EPOCH;
+
+// xorshift64*, plenty for CXNN and reproducible from a single seed
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift never leaves an all zero state
+        let state = seed ^ 0x9E37_79B9_7F4A_7C15;
+        Self {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    // the generator mid sequence, for save states to carry on from
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn from_state(state: u64) -> Self {
+        Self {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub fn time_seed() -> u64 {
+        UNIX_EPOCH.elapsed().expect("time travel").as_nanos() as u64
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
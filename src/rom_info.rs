@@ -0,0 +1,76 @@
+use std::fmt::Display;
+
+use crate::{crc32::crc32, options::Variant};
+
+#[derive(Debug, Clone)]
+pub struct RomInfo {
+    pub len: usize,
+    pub crc32: u32,
+    pub variant: Variant,
+}
+
+impl RomInfo {
+    pub fn inspect(rom: &[u8]) -> Self {
+        let variant = rom
+            .chunks_exact(2)
+            .map(|pair| guess_variant(u16::from_be_bytes([pair[0], pair[1]])))
+            .max()
+            .unwrap_or(Variant::Chip8);
+        Self {
+            len: rom.len(),
+            crc32: crc32(rom),
+            variant,
+        }
+    }
+}
+
+impl Display for RomInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes, crc32 {:08x}, looks like {}",
+            self.len, self.crc32, self.variant
+        )
+    }
+}
+
+// opcodes that only exist in the extended instruction sets
+fn guess_variant(instruction: u16) -> Variant {
+    match (
+        instruction >> 12,
+        instruction & 0x00FF,
+        instruction & 0x000F,
+    ) {
+        (0x0, 0xD0..=0xDF, _) if instruction & 0x0F00 == 0 => Variant::XoChip, // 00DN scroll up
+        (0x5, _, 0x2 | 0x3) => Variant::XoChip, // 5XY2 / 5XY3 range store / load
+        (0xF, 0x00 | 0x02, _) if instruction & 0x0F00 == 0 => Variant::XoChip, // F000 / F002
+        (0xF, 0x01 | 0x3A, _) => Variant::XoChip, // FN01 plane / FX3A pitch
+        (0x0, 0xC0..=0xCF | 0xFB..=0xFF, _) if instruction & 0x0F00 == 0 => Variant::SuperChip,
+        (0xD, _, 0x0) => Variant::SuperChip, // DXY0 16x16 sprite
+        (0xF, 0x30 | 0x75 | 0x85, _) => Variant::SuperChip,
+        _ => Variant::Chip8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sys_calls_stay_chip_8() {
+        for instruction in [0x00D4, 0x00C2, 0x00FF] {
+            assert_ne!(
+                guess_variant(instruction),
+                Variant::Chip8,
+                "{instruction:#06x}"
+            );
+        }
+        for instruction in [0x01FF, 0x03D4, 0x0EC2, 0x0F00] {
+            assert_eq!(
+                guess_variant(instruction),
+                Variant::Chip8,
+                "{instruction:#06x}"
+            );
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::{fs, io, path::Path, str};
+
+use chip_8_rs::{crc32::crc32, extensions::Extensions};
+
+use crate::{
+    input_log::InputReplay,
+    options::{Fill, Options, Variant},
+    quirks::Quirks,
+};
+
+const MAGIC: &[u8; 3] = b"C8S";
+const SCENARIO_VERSION: u8 = 2;
+const FILLS: [Fill; 4] = [Fill::Zero, Fill::Ff, Fill::Random, Fill::Pattern];
+
+// everything needed to play a moment back somewhere else: `C8S`, a version byte, the
+// rom's crc32, then the variant, raw image and vip boot flags, fill, seed, quirks and
+// extensions as `name=bool` pairs (no extensions for the variant's own), the rom, a
+// save state and input in the --record format, the last five length prefixed
+pub struct Scenario {
+    pub rom: Vec<u8>,
+    pub variant: Variant,
+    pub extensions: Option<Extensions>,
+    pub raw_image: bool,
+    pub vip_boot: bool,
+    pub fill: Fill,
+    pub seed: Option<u64>,
+    pub quirks: Quirks,
+    pub state: Vec<u8>,
+    pub input: String, // empty when nothing was recorded
+}
+
+impl Scenario {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::decode(&fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+
+    // the bundle's machine settings win over the command line's
+    pub fn apply(&self, options: &mut Options) {
+        options.variant = self.variant;
+        options.extensions = self.extensions;
+        options.raw_image = self.raw_image;
+        options.vip_boot = self.vip_boot;
+        options.fill = self.fill;
+        options.quirks = self.quirks.clone();
+        if self.seed.is_some() {
+            options.seed = self.seed;
+        }
+    }
+
+    pub fn replay(&self) -> Option<io::Result<InputReplay>> {
+        (!self.input.is_empty()).then(|| InputReplay::parse(&self.input))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let quirks = encode_flags(&Quirks::NAMES, |name| self.quirks.get(name));
+        let extensions = self.extensions.map_or_else(String::new, |extensions| {
+            encode_flags(&Extensions::NAMES, |name| extensions.get(name))
+        });
+        let mut bundle = MAGIC.to_vec();
+        bundle.push(SCENARIO_VERSION);
+        bundle.extend_from_slice(&crc32(&self.rom).to_be_bytes());
+        bundle.push(self.variant as u8);
+        bundle.push(self.raw_image as u8);
+        bundle.push(self.vip_boot as u8);
+        bundle.push(FILLS.iter().position(|&fill| fill == self.fill).unwrap() as u8);
+        bundle.push(self.seed.is_some() as u8);
+        bundle.extend_from_slice(&self.seed.unwrap_or(0).to_be_bytes());
+        for section in [
+            quirks.as_bytes(),
+            extensions.as_bytes(),
+            &self.rom,
+            &self.state,
+            self.input.as_bytes(),
+        ] {
+            bundle.extend_from_slice(&(section.len() as u32).to_be_bytes());
+            bundle.extend_from_slice(section);
+        }
+        bundle
+    }
+
+    fn decode(mut bundle: &[u8]) -> io::Result<Self> {
+        let mut take = |len: usize| {
+            if bundle.len() < len {
+                return Err(invalid("scenario ended unexpectedly"));
+            }
+            let (head, tail) = bundle.split_at(len);
+            bundle = tail;
+            Ok(head)
+        };
+        if take(3)? != MAGIC {
+            return Err(invalid("not a scenario file"));
+        }
+        let version = take(1)?[0];
+        if version != SCENARIO_VERSION {
+            return Err(invalid(&format!("unsupported scenario version {version}")));
+        }
+        let rom_hash = u32::from_be_bytes(take(4)?.try_into().unwrap());
+        let variant = match take(1)?[0] {
+            0 => Variant::Chip8,
+            1 => Variant::SuperChip,
+            2 => Variant::XoChip,
+            variant => return Err(invalid(&format!("unknown variant {variant}"))),
+        };
+        let raw_image = take(1)?[0] != 0;
+        let vip_boot = take(1)?[0] != 0;
+        let fill = match take(1)?[0] {
+            fill if (fill as usize) < FILLS.len() => FILLS[fill as usize],
+            fill => return Err(invalid(&format!("unknown fill {fill}"))),
+        };
+        let has_seed = take(1)?[0] != 0;
+        let seed = u64::from_be_bytes(take(8)?.try_into().unwrap());
+        let mut section = || {
+            let len = u32::from_be_bytes(take(4)?.try_into().unwrap());
+            take(len as usize)
+        };
+        let quirk_text = section()?;
+        let extension_text = section()?;
+        let rom = section()?.to_vec();
+        let state = section()?.to_vec();
+        let input = section()?;
+
+        if crc32(&rom) != rom_hash {
+            return Err(invalid("the bundled rom doesn't match its hash"));
+        }
+        let mut quirks = Quirks::default();
+        decode_flags(quirk_text, "quirk", |name, value| quirks.set(name, value))?;
+        let extensions = if extension_text.is_empty() {
+            None
+        } else {
+            let mut extensions = Extensions::default();
+            decode_flags(extension_text, "extension", |name, value| {
+                extensions.set(name, value)
+            })?;
+            Some(extensions)
+        };
+        Ok(Self {
+            rom,
+            variant,
+            extensions,
+            raw_image,
+            vip_boot,
+            fill,
+            seed: has_seed.then_some(seed),
+            quirks,
+            state,
+            input: String::from_utf8(input.to_vec()).map_err(|_| invalid("bad input"))?,
+        })
+    }
+}
+
+fn encode_flags(names: &[&str], get: impl Fn(&str) -> Option<bool>) -> String {
+    names
+        .iter()
+        .map(|name| format!("{}={}", name, get(name).unwrap()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn decode_flags(
+    text: &[u8],
+    kind: &str,
+    mut set: impl FnMut(&str, bool) -> bool,
+) -> io::Result<()> {
+    let bad = || invalid(&format!("bad {kind}s"));
+    let text = str::from_utf8(text).map_err(|_| bad())?;
+    for flag in text.split(',').filter(|flag| !flag.is_empty()) {
+        let (name, value) = flag
+            .split_once('=')
+            .and_then(|(name, value)| Some((name, value.parse().ok()?)))
+            .ok_or_else(bad)?;
+        // one from a newer build is left at its default
+        if !set(name, value) {
+            log::warn!("Scenario sets unknown {} {}", kind, name);
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario() -> Scenario {
+        let mut quirks = Quirks::default();
+        quirks.set(Quirks::NAMES[0], true);
+        Scenario {
+            rom: vec![0x12, 0x00],
+            variant: Variant::SuperChip,
+            extensions: Some(Extensions {
+                xochip_ranges: true,
+                ..Extensions::default()
+            }),
+            raw_image: true,
+            vip_boot: true,
+            fill: Fill::Pattern,
+            seed: Some(0x1234_5678_9ABC),
+            quirks,
+            state: vec![1, 2, 3],
+            input: "0 press 5\n".to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trips_every_setting() {
+        let original = scenario();
+        let decoded = Scenario::decode(&original.encode()).unwrap();
+        assert_eq!(decoded.rom, original.rom);
+        assert_eq!(decoded.variant, original.variant);
+        assert_eq!(decoded.extensions, original.extensions);
+        assert_eq!(decoded.raw_image, original.raw_image);
+        assert_eq!(decoded.vip_boot, original.vip_boot);
+        assert_eq!(decoded.fill, original.fill);
+        assert_eq!(decoded.seed, original.seed);
+        for name in Quirks::NAMES {
+            assert_eq!(
+                decoded.quirks.get(name),
+                original.quirks.get(name),
+                "{name}"
+            );
+        }
+        assert_eq!(decoded.state, original.state);
+        assert_eq!(decoded.input, original.input);
+
+        let mut options = Options::default();
+        decoded.apply(&mut options);
+        assert_eq!(options.extensions(), original.extensions.unwrap());
+        assert!(options.vip_boot);
+        assert_eq!(options.fill, Fill::Pattern);
+    }
+
+    #[test]
+    fn variant_extensions_stay_unset() {
+        let original = Scenario {
+            extensions: None,
+            ..scenario()
+        };
+        let decoded = Scenario::decode(&original.encode()).unwrap();
+        assert_eq!(decoded.extensions, None);
+    }
+
+    #[test]
+    fn rejects_older_versions_and_bad_roms() {
+        let mut bundle = scenario().encode();
+        bundle[3] = 1;
+        assert!(Scenario::decode(&bundle).is_err());
+        let mut bundle = scenario().encode();
+        bundle[4] ^= 0xFF; // the rom's crc32
+        assert!(Scenario::decode(&bundle).is_err());
+    }
+}
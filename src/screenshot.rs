@@ -0,0 +1,13 @@
+use std::{fs, path::Path};
+
+use crate::{chip_8_variant::Chip8Variant, palette::Palette, png};
+
+// the display as it stands, in the palette's off and on colours
+pub fn save(chip_8: &dyn Chip8Variant, palette: &Palette, path: &Path) {
+    let (width, height) = chip_8.resolution();
+    let image = png::encode(width, height, chip_8.display(), palette.off_on());
+    match fs::write(path, image) {
+        Ok(()) => log::info!("Saved screenshot to {}", path.display()),
+        Err(err) => log::error!("Failed to save screenshot: {}", err),
+    }
+}
@@ -0,0 +1,10 @@
+use crate::fault::Fault;
+
+// things the machine does that the frontend has to act on beyond drawing, queued until
+// polled, the oldest are dropped once a frontend that never polls lets them pile up
+#[derive(Debug, Clone, Copy)]
+pub enum SystemEvent {
+    Exit, // 00FD, the rom is done
+    Fault(Fault),
+    ResolutionChanged { hires: bool }, // 00FE / 00FF or a reset switched modes
+}
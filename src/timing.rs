@@ -0,0 +1,38 @@
+// COSMAC VIP instruction costs in 1802 machine cycles (8 clocks of the 1.76 MHz crystal),
+// rounded from Laurence Scotford's walkthrough of the VIP interpreter. Every instruction
+// also pays the interpreter's fetch and decode, and data dependent instructions are
+// charged their typical case
+const FETCH: u32 = 40;
+
+// a 60 Hz frame is 3668 machine cycles, the 1861 takes 1024 of them for display DMA
+pub const FRAME_CYCLES: f64 = 2644.0;
+
+pub fn vip_cycles(ir: u16) -> u32 {
+    let x = (ir >> 8 & 0xF) as u32;
+    let n = (ir & 0xF) as u32;
+    FETCH
+        + match ir >> 12 {
+            0x0 => match ir {
+                0x00E0 => 24 + 256 * 4, // clears the display page a byte at a time
+                _ => 10,
+            },
+            0x1 => 12,
+            0x2 => 26,
+            0x3 | 0x4 => 10,
+            0x5 | 0x9 => 14,
+            0x6 => 6,
+            0x7 => 10,
+            0x8 => 44,
+            0xA => 12,
+            0xB => 22,
+            0xC => 36,
+            0xD => 26 + 68 * n, // each row is shifted into place and xored across two bytes
+            0xE => 14,
+            _ => match ir & 0xFF {
+                0x1E | 0x29 => 16,
+                0x33 => 84,
+                0x55 | 0x65 => 14 + 14 * (x + 1),
+                _ => 10,
+            },
+        }
+}
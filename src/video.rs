@@ -0,0 +1,135 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use crate::{chip_8_variant::Chip8Variant, palette::Palette, png};
+
+// every resolution is 2:1, so scaling to a fixed width keeps one canvas size
+const WIDTH: usize = 512;
+const HEIGHT: usize = WIDTH / 2;
+const MAX_DURATION: Duration = Duration::from_secs(5 * 60);
+
+// the display once per refresh, kept at its own resolution with repeats merged into
+// the previous frame's refreshes, and upscaled only when the animation is written
+pub struct VideoRecorder {
+    path: PathBuf,
+    frames: Vec<Frame>,
+    duration: Duration,
+}
+
+struct Frame {
+    display: Vec<u8>,
+    width: usize,
+    refreshes: u16, // shown for this many refreshes at refresh_hz
+    refresh_hz: u16,
+}
+
+impl VideoRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            frames: Vec::new(),
+            duration: Duration::ZERO,
+        }
+    }
+
+    pub fn capture(&mut self, chip_8: &dyn Chip8Variant, refresh: Duration) {
+        if self.duration >= MAX_DURATION {
+            return;
+        }
+        self.duration += refresh;
+        if self.duration >= MAX_DURATION {
+            log::warn!(
+                "Stopped recording video at the {} second limit",
+                MAX_DURATION.as_secs()
+            );
+        }
+
+        // a whole number of refreshes over the rate keeps 60 Hz frames from drifting
+        // the way rounding each one to milliseconds would
+        let refresh_hz = (1.0 / refresh.as_secs_f64()).round() as u16;
+        let (width, _) = chip_8.resolution();
+        let display = chip_8.display();
+        if let Some(last) = self.frames.last_mut() {
+            if last.width == width
+                && last.display == display
+                && last.refresh_hz == refresh_hz
+                && last.refreshes < u16::MAX
+            {
+                last.refreshes += 1;
+                return;
+            }
+        }
+        self.frames.push(Frame {
+            display: display.to_vec(),
+            width,
+            refreshes: 1,
+            refresh_hz,
+        });
+    }
+
+    pub fn save(&self, palette: &Palette) {
+        if self.frames.is_empty() {
+            return;
+        }
+        let frames: Vec<_> = self
+            .frames
+            .iter()
+            .map(|frame| {
+                let delay = (frame.refreshes, frame.refresh_hz);
+                (upscale(&frame.display, frame.width), delay)
+            })
+            .collect();
+        let image = png::encode_animation(WIDTH, HEIGHT, &frames, palette.off_on());
+        match fs::write(&self.path, image) {
+            Ok(()) => log::info!(
+                "Saved {} frames of video to {}",
+                frames.len(),
+                self.path.display()
+            ),
+            Err(err) => log::error!("Failed to save video: {}", err),
+        }
+    }
+}
+
+fn upscale(display: &[u8], width: usize) -> Vec<u8> {
+    let scale = WIDTH / width;
+    let stride = width / 8;
+    let mut rows = vec![0; HEIGHT * WIDTH / 8];
+    for (y, row) in rows.chunks_exact_mut(WIDTH / 8).enumerate() {
+        let source = &display[y / scale * stride..][..stride];
+        for x in 0..WIDTH {
+            let source_x = x / scale;
+            if source[source_x / 8] & 0x80 >> (source_x % 8) != 0 {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_8::Chip8;
+
+    #[test]
+    fn repeats_add_whole_refreshes() {
+        let refresh = Duration::from_secs_f64(1.0 / 60.0);
+        // draw the 0 glyph on the second instruction
+        let mut chip_8 = Chip8::from_rom(&[0xA0, 0x00, 0xD0, 0x05], Default::default());
+        let mut video = VideoRecorder::new(PathBuf::new());
+        chip_8.instruction_cycle();
+        for _ in 0..3 {
+            video.capture(&chip_8, refresh);
+        }
+        chip_8.instruction_cycle();
+        video.capture(&chip_8, refresh);
+        video.capture(&chip_8, Duration::from_secs_f64(1.0 / 120.0));
+
+        let delays: Vec<_> = video
+            .frames
+            .iter()
+            .map(|frame| (frame.refreshes, frame.refresh_hz))
+            .collect();
+        assert_eq!(delays, [(3, 60), (1, 60), (1, 120)]);
+    }
+}
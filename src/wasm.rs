@@ -0,0 +1,123 @@
+use std::cell::RefCell;
+
+use crate::{
+    chip_8::Chip8,
+    chip_8_variant::Chip8Variant,
+    options::{Options, Variant},
+};
+
+// C ABI entry points for a JavaScript frontend, one machine per module instance. Write
+// the rom through `rom_buffer`, call `load`, then `step` once per 60 Hz frame and draw
+// `framebuffer`, a row major bitmap with 8 pixels per byte, most significant bit first
+#[derive(Default)]
+struct Frontend {
+    rom: Vec<u8>,
+    chip_8: Option<Chip8>,
+    sound_callback: Option<extern "C" fn(bool)>,
+    beeping: bool,
+}
+
+thread_local! {
+    static FRONTEND: RefCell<Frontend> = RefCell::new(Frontend::default());
+}
+
+fn with_chip_8<T: Default>(f: impl FnOnce(&mut Chip8) -> T) -> T {
+    FRONTEND.with(|frontend| {
+        frontend
+            .borrow_mut()
+            .chip_8
+            .as_mut()
+            .map(f)
+            .unwrap_or_default()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rom_buffer(len: usize) -> *mut u8 {
+    FRONTEND.with(|frontend| {
+        let rom = &mut frontend.borrow_mut().rom;
+        rom.resize(len, 0);
+        rom.as_mut_ptr()
+    })
+}
+
+// variant 0 is CHIP-8, 1 SUPER-CHIP and 2 XO-CHIP, the seed has to come from the page
+// since there's no clock to take one from
+#[no_mangle]
+pub extern "C" fn load(variant: u32, seed: u32) -> bool {
+    let variant = match variant {
+        0 => Variant::Chip8,
+        1 => Variant::SuperChip,
+        2 => Variant::XoChip,
+        _ => return false,
+    };
+    let options = Options {
+        variant,
+        seed: Some(seed as u64),
+        ..Options::default()
+    };
+    FRONTEND.with(|frontend| {
+        let mut frontend = frontend.borrow_mut();
+        let chip_8 = Chip8::from_rom(&frontend.rom, options);
+        frontend.chip_8 = Some(chip_8);
+        frontend.beeping = false;
+    });
+    true
+}
+
+// runs `cycles` instructions and one timer tick, the sound callback hears about the
+// beep starting and stopping once the machine is released, so it may call back in
+#[no_mangle]
+pub extern "C" fn step(cycles: u32) {
+    let sound_change = FRONTEND.with(|frontend| {
+        let mut frontend = frontend.borrow_mut();
+        let chip_8 = frontend.chip_8.as_mut()?;
+        for _ in 0..cycles {
+            if chip_8.waiting() || chip_8.halted() {
+                break;
+            }
+            chip_8.instruction_cycle();
+        }
+        while chip_8.poll_draw_queue().is_some() {}
+        chip_8.decrement_timers();
+        let beeping = chip_8.sound_active();
+        if beeping == frontend.beeping {
+            return None;
+        }
+        frontend.beeping = beeping;
+        Some((frontend.sound_callback?, beeping))
+    });
+    if let Some((callback, beeping)) = sound_change {
+        callback(beeping);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn framebuffer() -> *const u8 {
+    with_chip_8(|chip_8| Some(chip_8.display().as_ptr())).unwrap_or(std::ptr::null())
+}
+
+#[no_mangle]
+pub extern "C" fn framebuffer_width() -> u32 {
+    with_chip_8(|chip_8| chip_8.resolution().0 as u32)
+}
+
+#[no_mangle]
+pub extern "C" fn framebuffer_height() -> u32 {
+    with_chip_8(|chip_8| chip_8.resolution().1 as u32)
+}
+
+#[no_mangle]
+pub extern "C" fn key_down(key: u32) {
+    with_chip_8(|chip_8| chip_8.set_key(key as usize & 0xF, true));
+}
+
+#[no_mangle]
+pub extern "C" fn key_up(key: u32) {
+    with_chip_8(|chip_8| chip_8.set_key(key as usize & 0xF, false));
+}
+
+#[no_mangle]
+pub extern "C" fn set_sound_callback(callback: extern "C" fn(bool)) {
+    FRONTEND.with(|frontend| frontend.borrow_mut().sound_callback = Some(callback));
+}